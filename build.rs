@@ -0,0 +1,66 @@
+use std::env;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=CC");
+    if env::var_os("CARGO_FEATURE_SECCOMP").is_none() {
+        return;
+    }
+
+    // The names of the syscalls are only used to report seccomp violations,
+    // unknown syscalls are reported by their number.
+    let names = syscall_names().unwrap_or_else(|err| {
+        println!("cargo:warning=syscall names are unavailable: {err}");
+        vec![]
+    });
+    let mut table = String::from("&[\n");
+    for (nr, name) in names {
+        writeln!(table, "    ({nr}, {name:?}),").unwrap();
+    }
+    table.push_str("]\n");
+
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    std::fs::write(out.join("syscall_names.rs"), table).unwrap();
+}
+
+/// Get the syscalls of the target from the `__NR_*` macros of the kernel headers.
+fn syscall_names() -> Result<Vec<(i64, String)>, String> {
+    let target = env::var("TARGET").unwrap();
+    let host = env::var("HOST").unwrap();
+    let target_cc = env::var(format!("CC_{}", target.replace('-', "_"))).ok();
+    let cc = match (target_cc, env::var("CC").ok()) {
+        (Some(cc), _) => cc,
+        (None, Some(cc)) => cc,
+        (None, None) if target == host => "cc".to_owned(),
+        (None, None) => return Err(format!("no C compiler for {target}")),
+    };
+
+    let mut child = Command::new(&cc)
+        .args(["-E", "-dM", "-x", "c", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("failed to run {cc}: {err}"))?;
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"#include <asm/unistd.h>\n").unwrap();
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    if !output.status.success() {
+        return Err(format!("{cc} failed with {}", output.status));
+    }
+
+    let macros = String::from_utf8_lossy(&output.stdout);
+    let mut names: Vec<_> = macros
+        .lines()
+        .filter_map(|line| {
+            let (name, nr) = line.strip_prefix("#define __NR_")?.split_once(' ')?;
+            Some((nr.trim().parse().ok()?, name.to_owned()))
+        })
+        .collect();
+    names.sort();
+    Ok(names)
+}
@@ -0,0 +1,463 @@
+use std::cell::RefCell;
+use std::ffi::{CString, OsStr};
+use std::fs::{File, OpenOptions};
+use std::io::{self, IoSlice, IoSliceMut};
+use std::os::fd::{AsRawFd as _, FromRawFd as _, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt as _;
+use std::os::unix::fs::OpenOptionsExt as _;
+use std::os::unix::net::UnixStream;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use libc::SIGCHLD;
+use nix::sys::resource::{getrlimit, Resource};
+use nix::sys::socket::{
+    recvmsg, send, sendmsg, socketpair, AddressFamily, ControlMessage, ControlMessageOwned,
+    MsgFlags, SockFlag, SockType,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::clone::clone3_or_clone;
+use crate::command::close_range;
+use crate::{Error, WireError, WireFd, Zygote};
+
+thread_local! {
+    static BROKER: RefCell<Option<Broker>> = const { RefCell::new(None) };
+}
+
+/// The flags allowed by a [`BrokerPolicy`] by default.
+const DEFAULT_FLAGS: i32 = libc::O_CLOEXEC
+    | libc::O_NONBLOCK
+    | libc::O_NOFOLLOW
+    | libc::O_NOCTTY
+    | libc::O_DIRECTORY
+    | libc::O_CREAT
+    | libc::O_EXCL
+    | libc::O_TRUNC
+    | libc::O_APPEND;
+
+/// The policy a [`Broker`] checks requests against.
+///
+/// Paths are matched component-wise against the allowed prefixes, and they
+/// must be absolute and free of `..` components. Symbolic links are followed
+/// as long as they don't point outside of the allowed prefix.
+/// Denied requests fail with `EACCES`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BrokerPolicy {
+    read_only: Vec<PathBuf>,
+    read_write: Vec<PathBuf>,
+    flags: i32,
+}
+
+impl Default for BrokerPolicy {
+    fn default() -> Self {
+        Self {
+            read_only: vec![],
+            read_write: vec![],
+            flags: DEFAULT_FLAGS,
+        }
+    }
+}
+
+impl BrokerPolicy {
+    /// Create a policy that denies access to every path.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow opening files beneath `path` for reading.
+    pub fn read_only(mut self, path: impl Into<PathBuf>) -> Self {
+        self.read_only.push(path.into());
+        self
+    }
+
+    /// Allow opening, creating and truncating files beneath `path`
+    /// for reading and writing.
+    pub fn read_write(mut self, path: impl Into<PathBuf>) -> Self {
+        self.read_write.push(path.into());
+        self
+    }
+
+    /// Set the `open` flags that requests can use, besides the access mode,
+    /// e.g., `O_CREAT` or `O_APPEND`. Requests using any other flag are denied.
+    /// Defaults to `O_CLOEXEC`, `O_NONBLOCK`, `O_NOFOLLOW`, `O_NOCTTY`,
+    /// `O_DIRECTORY`, `O_CREAT`, `O_EXCL`, `O_TRUNC` and `O_APPEND`.
+    pub fn flags(mut self, flags: i32) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Check a request, returning the prefix to open it from.
+    fn check(&self, path: &Path, flags: i32) -> io::Result<&Path> {
+        let denied = || io::Error::from_raw_os_error(libc::EACCES);
+        if !path.is_absolute() || path.components().any(|c| c == Component::ParentDir) {
+            return Err(denied());
+        }
+        if flags & !(libc::O_ACCMODE | self.flags) != 0 {
+            return Err(denied());
+        }
+        let write = flags & libc::O_ACCMODE != libc::O_RDONLY
+            || flags & (libc::O_CREAT | libc::O_TRUNC) != 0;
+        let read_only = self.read_only.iter().filter(|_| !write);
+        self.read_write
+            .iter()
+            .chain(read_only)
+            .find(|prefix| path.starts_with(prefix))
+            .map(PathBuf::as_path)
+            .ok_or_else(denied)
+    }
+
+    /// Open `path` if the policy allows it.
+    fn open(&self, path: &Path, flags: i32) -> io::Result<File> {
+        let prefix = self.check(path, flags)?;
+        let path = path.strip_prefix(prefix).unwrap();
+        let prefix = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_PATH | libc::O_DIRECTORY)
+            .open(prefix)?;
+        let path = match path.as_os_str().is_empty() {
+            true => CString::new(".").unwrap(),
+            false => CString::new(path.as_os_str().as_bytes())?,
+        };
+        let mut how: libc::open_how = unsafe { std::mem::zeroed() };
+        how.flags = (flags | libc::O_CLOEXEC) as u64;
+        if flags & libc::O_CREAT != 0 {
+            how.mode = 0o666;
+        }
+        // resolve the path without escaping the prefix, e.g., through symlinks
+        how.resolve = libc::RESOLVE_BENEATH | libc::RESOLVE_NO_MAGICLINKS;
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_openat2,
+                prefix.as_raw_fd(),
+                path.as_ptr(),
+                &how,
+                size_of::<libc::open_how>(),
+            )
+        };
+        match fd {
+            -1 => match io::Error::last_os_error() {
+                err if err.raw_os_error() == Some(libc::EXDEV) => {
+                    Err(io::Error::from_raw_os_error(libc::EACCES))
+                }
+                err => Err(err),
+            },
+            fd => Ok(unsafe { File::from_raw_fd(fd as RawFd) }),
+        }
+    }
+}
+
+/// A privileged broker that opens files on behalf of sandboxed zygotes.
+///
+/// The broker serves requests from a helper process, forked from a zygote
+/// and running with the same privileges, and checks them against a
+/// [`BrokerPolicy`]. Up to a fixed number of requests are served at the
+/// same time, others wait for their turn. This lets a zygote with
+/// restricted filesystem access, e.g., through a Landlock policy, still open
+/// the files the broker allows.
+///
+/// A broker handle can be sent to a zygote, e.g., as the argument of a task,
+/// or it can be set for every task of a zygote with [`ZygoteBuilder::broker()`](crate::ZygoteBuilder::broker),
+/// and then obtained with [`Broker::current()`].
+/// Requests can be made concurrently from any number of threads and processes.
+/// The broker stops once every handle to it has been dropped.
+///
+/// ```rust
+/// # use std::io::Read as _;
+/// # use zygote::{Broker, BrokerPolicy, WireError, Zygote};
+/// let broker = Broker::new(BrokerPolicy::new().read_only("/etc")).unwrap();
+/// let zygote = Zygote::builder().broker(broker).build().unwrap();
+///
+/// let res = zygote.run(|_| -> Result<String, WireError> {
+///     let broker = Broker::current().unwrap();
+///     let mut file = broker.open("/etc/passwd", libc::O_RDONLY)?;
+///     let mut content = String::new();
+///     file.read_to_string(&mut content)?;
+///     Ok(content)
+/// }, ());
+/// assert!(res.unwrap().contains("root"));
+///
+/// let res = zygote.run(|_| -> Result<(), WireError> {
+///     Broker::current().unwrap().open("/etc/passwd", libc::O_WRONLY)?;
+///     Ok(())
+/// }, ());
+/// assert_eq!(res.unwrap_err().raw_os_error(), Some(libc::EACCES));
+/// ```
+#[derive(Serialize, Deserialize)]
+pub struct Broker(WireFd<Arc<OwnedFd>>);
+
+impl Broker {
+    /// Start a new broker that checks requests against `policy`,
+    /// from the global zygote, see [`Zygote::global()`].
+    ///
+    /// # Panics
+    /// Same panic conditions as [`Zygote::global()`].
+    #[track_caller]
+    pub fn new(policy: BrokerPolicy) -> Result<Broker, Error> {
+        Self::spawn(policy, Zygote::global())
+    }
+
+    /// Start a new broker that checks requests against `policy`,
+    /// from within `zygote`.
+    ///
+    /// The broker process is forked from the zygote, like a [`Command`](crate::Command),
+    /// so it's safe to start one from a multithreaded process. It runs with
+    /// the privileges of the zygote, and it outlives it, unless the zygote
+    /// was created with [`ZygoteBuilder::kill_tree()`](crate::ZygoteBuilder::kill_tree).
+    #[track_caller]
+    pub fn spawn(policy: BrokerPolicy, zygote: &Zygote) -> Result<Broker, Error> {
+        let (client, server) = UnixStream::pair()?;
+        zygote.try_run(start, (WireFd::new(server), policy))??;
+        Ok(Broker(WireFd::new(Arc::new(client.into()))))
+    }
+
+    /// Get the broker set for the current zygote with
+    /// [`ZygoteBuilder::broker()`](crate::ZygoteBuilder::broker), if any.
+    ///
+    /// Zygotes spawned from a zygote with a broker use the same broker,
+    /// unless they are configured with a different one.
+    pub fn current() -> Option<Broker> {
+        BROKER.with_borrow(|broker| broker.clone())
+    }
+
+    /// Ask the broker to open `path` with the given `open` `flags`,
+    /// e.g., `O_RDONLY`. The returned file is always close-on-exec.
+    ///
+    /// Requests denied by the broker policy fail with `EACCES`.
+    pub fn open(&self, path: impl AsRef<Path>, flags: i32) -> Result<WireFd<File>, WireError> {
+        let path = path.as_ref();
+        let (request, remote) = socketpair(
+            AddressFamily::Unix,
+            SockType::SeqPacket,
+            None,
+            SockFlag::SOCK_CLOEXEC,
+        )?;
+        // queue the request before handing the socket over, the broker won't wait for it
+        let mut msg = flags.to_ne_bytes().to_vec();
+        msg.extend_from_slice(path.as_os_str().as_bytes());
+        send(request.as_raw_fd(), &msg, MsgFlags::empty())?;
+        // a single message, so that concurrent requests don't get mixed up
+        sendmsg::<()>(
+            self.0.as_raw_fd(),
+            &[IoSlice::new(&[0])],
+            &[ControlMessage::ScmRights(&[remote.as_raw_fd()])],
+            MsgFlags::empty(),
+            None,
+        )?;
+        drop(remote);
+
+        let mut errno = [0u8; size_of::<i32>()];
+        let mut iov = [IoSliceMut::new(&mut errno)];
+        let mut cmsg = nix::cmsg_space!([RawFd; 1]);
+        let msg = recvmsg::<()>(
+            request.as_raw_fd(),
+            &mut iov,
+            Some(&mut cmsg),
+            MsgFlags::MSG_CMSG_CLOEXEC,
+        )?;
+        let mut files = vec![];
+        for cmsg in msg.cmsgs()? {
+            if let ControlMessageOwned::ScmRights(fds) = cmsg {
+                files.extend(fds.into_iter().map(|fd| unsafe { File::from_raw_fd(fd) }));
+            }
+        }
+        if msg.bytes != errno.len() {
+            return Err(WireError::from_str("the broker is gone"));
+        }
+        match (i32::from_ne_bytes(errno), files.pop()) {
+            (0, Some(file)) => Ok(WireFd::new(file)),
+            (errno, _) => Err(WireError::from(io::Error::from_raw_os_error(errno))
+                .context(format_args!("open {path:?}"))),
+        }
+    }
+}
+
+impl Clone for Broker {
+    fn clone(&self) -> Self {
+        Broker(WireFd::new(Arc::clone(&self.0)))
+    }
+}
+
+/// Set the broker of the current zygote.
+pub(crate) fn set_current(broker: Option<Broker>) {
+    BROKER.set(broker);
+}
+
+/// Serve the requests on `server` from a new process, this runs in the zygote.
+/// The process is forked twice, so that it isn't a child of the zygote, and
+/// it doesn't need to be reaped.
+fn start((server, policy): (WireFd<UnixStream>, BrokerPolicy)) -> Result<(), WireError> {
+    let server = server.into_inner();
+    match clone3_or_clone(0, SIGCHLD, None)? {
+        None => {
+            let code = match clone3_or_clone(0, SIGCHLD, None) {
+                Ok(None) => {
+                    // the broker only keeps its socket, and the standard streams
+                    let fd = server.as_raw_fd();
+                    let max_fd = match getrlimit(Resource::RLIMIT_NOFILE) {
+                        Ok((soft, _)) => soft.min(RawFd::MAX as u64) as RawFd,
+                        Err(_) => 1024,
+                    };
+                    unsafe {
+                        close_range(3, fd - 1, max_fd);
+                        close_range(fd + 1, RawFd::MAX, max_fd);
+                    }
+                    serve(server, policy);
+                    0
+                }
+                Ok(Some(_)) => 0,
+                Err(_) => 1,
+            };
+            // don't run anything the calling process registered to run at exit
+            unsafe { libc::_exit(code) };
+        }
+        Some(child) => match child.wait()? {
+            Some(status) if status.success() => Ok(()),
+            _ => Err(WireError::from_str("failed to start the broker")),
+        },
+    }
+}
+
+/// The number of requests the broker serves at the same time.
+const WORKERS: usize = 4;
+
+fn serve(server: UnixStream, policy: BrokerPolicy) {
+    // opening a file can block, e.g., on a FIFO, don't hold up every request,
+    // but don't let clients start an unbounded number of threads either
+    std::thread::scope(|scope| {
+        for _ in 1..WORKERS {
+            let _ = std::thread::Builder::new()
+                .name("zygote-broker".into())
+                .spawn_scoped(scope, || serve_requests(&server, &policy));
+        }
+        serve_requests(&server, &policy);
+    });
+}
+
+/// Serve requests on `server` until every client is gone.
+fn serve_requests(server: &UnixStream, policy: &BrokerPolicy) {
+    let mut cmsg = nix::cmsg_space!([RawFd; 1]);
+    loop {
+        let mut buf = [0u8];
+        let mut iov = [IoSliceMut::new(&mut buf)];
+        let msg = recvmsg::<()>(
+            server.as_raw_fd(),
+            &mut iov,
+            Some(&mut cmsg),
+            MsgFlags::MSG_CMSG_CLOEXEC,
+        );
+        let Ok(msg) = msg else {
+            return;
+        };
+        if msg.bytes == 0 {
+            // every client is gone
+            return;
+        }
+        let Ok(cmsgs) = msg.cmsgs() else {
+            continue;
+        };
+        for cmsg in cmsgs {
+            if let ControlMessageOwned::ScmRights(fds) = cmsg {
+                for fd in fds {
+                    let request = unsafe { OwnedFd::from_raw_fd(fd) };
+                    serve_request(request, policy);
+                }
+            }
+        }
+    }
+}
+
+/// Serve the request queued on the socket `request`, sending back
+/// the errno of the request, and the file if it was allowed.
+fn serve_request(request: OwnedFd, policy: &BrokerPolicy) {
+    let res = read_request(&request).and_then(|(path, flags)| policy.open(&path, flags));
+    let (errno, fds) = match &res {
+        Ok(file) => (0, vec![file.as_raw_fd()]),
+        Err(err) => (err.raw_os_error().unwrap_or(libc::EIO), vec![]),
+    };
+    let cmsgs = match fds.is_empty() {
+        true => vec![],
+        false => vec![ControlMessage::ScmRights(&fds)],
+    };
+    let _ = sendmsg::<()>(
+        request.as_raw_fd(),
+        &[IoSlice::new(&errno.to_ne_bytes())],
+        &cmsgs,
+        MsgFlags::MSG_DONTWAIT | MsgFlags::MSG_NOSIGNAL,
+        None,
+    );
+}
+
+/// Read a request: the `open` flags, followed by the path, in a single message.
+/// The request comes from a sandboxed process, and it's not trusted.
+fn read_request(request: &OwnedFd) -> io::Result<(PathBuf, i32)> {
+    let invalid = || io::Error::from_raw_os_error(libc::EINVAL);
+    let mut buf = [0u8; size_of::<i32>() + libc::PATH_MAX as usize];
+    // the request must be queued already, and `MSG_TRUNC` reports the length of longer requests
+    let flags = libc::MSG_DONTWAIT | libc::MSG_TRUNC;
+    let len = unsafe {
+        libc::recv(
+            request.as_raw_fd(),
+            buf.as_mut_ptr() as *mut _,
+            buf.len(),
+            flags,
+        )
+    };
+    let len = usize::try_from(len).map_err(|_| io::Error::last_os_error())?;
+    if len <= size_of::<i32>() || len > buf.len() {
+        return Err(invalid());
+    }
+    let (flags, path) = buf[..len].split_at(size_of::<i32>());
+    let flags = i32::from_ne_bytes(flags.try_into().unwrap());
+    Ok((PathBuf::from(OsStr::from_bytes(path)), flags))
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::IoSlice;
+    use std::os::fd::{AsRawFd as _, OwnedFd};
+
+    use nix::sys::socket::{
+        send, sendmsg, socketpair, AddressFamily, ControlMessage, MsgFlags, SockFlag, SockType,
+    };
+
+    use super::{Broker, BrokerPolicy};
+
+    /// Send a raw `request` to the `broker`, returning our end of the request socket.
+    fn send_request(broker: &Broker, request: Option<&[u8]>) -> OwnedFd {
+        let (local, remote) = socketpair(
+            AddressFamily::Unix,
+            SockType::SeqPacket,
+            None,
+            SockFlag::SOCK_CLOEXEC,
+        )
+        .unwrap();
+        if let Some(request) = request {
+            send(local.as_raw_fd(), request, MsgFlags::empty()).unwrap();
+        }
+        sendmsg::<()>(
+            broker.0.as_raw_fd(),
+            &[IoSlice::new(&[0])],
+            &[ControlMessage::ScmRights(&[remote.as_raw_fd()])],
+            MsgFlags::empty(),
+            None,
+        )
+        .unwrap();
+        local
+    }
+
+    #[test]
+    fn bad_requests() {
+        let broker = Broker::new(BrokerPolicy::new().read_only("/etc")).unwrap();
+
+        // a client that never sends its request
+        let _idle = send_request(&broker, None);
+        // requests that are too short, or too long
+        let _short = send_request(&broker, Some(&[1, 2]));
+        let _long = send_request(&broker, Some(&vec![b'/'; 1 << 16]));
+        // a message without a request socket
+        send(broker.0.as_raw_fd(), &[0], MsgFlags::empty()).unwrap();
+
+        broker.open("/etc/passwd", libc::O_RDONLY).unwrap();
+    }
+}
@@ -0,0 +1,464 @@
+use std::time::Duration;
+
+use nix::sys::resource::{setrlimit, Resource as RawResource};
+use serde::{Deserialize, Serialize};
+
+use crate::namespace::{self, Ids};
+use crate::privileges::Privileges;
+use crate::process::{self, Process};
+use crate::{
+    observer, Broker, Capability, Cgroup, Error, Mounts, Relation, Transfer, WireError, Zygote,
+};
+
+/// What happens to the zygote process when its [`Zygote`] handle is dropped.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Terminate the zygote with `SIGKILL` and wait for it to exit.
+    #[default]
+    Kill,
+    /// Ask the zygote to exit and wait for it, see [`Zygote::shutdown()`].
+    /// The zygote is killed with `SIGKILL` if it doesn't exit within
+    /// the given timeout.
+    Graceful(Duration),
+    /// Leave the zygote running. It will exit on its own once it has
+    /// finished its current task, unless it is listening for connections
+    /// (see [`Zygote::listen()`]). The zygote still dies with its parent
+    /// process, see [`ZygoteBuilder::parent_death_signal()`].
+    Detach,
+}
+
+/// A resource whose usage can be limited with [`ZygoteBuilder::rlimit()`].
+///
+/// See [setrlimit(2)](https://man7.org/linux/man-pages/man2/setrlimit.2.html)
+/// for the details of each limit.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resource {
+    /// Maximum size of the virtual memory of the process in bytes (`RLIMIT_AS`)
+    AddressSpace,
+    /// Maximum CPU time of the process in seconds (`RLIMIT_CPU`).
+    /// The process receives `SIGXCPU` when it reaches the soft limit, and
+    /// `SIGKILL` when it reaches the hard limit.
+    CpuTime,
+    /// Maximum number of open file descriptors (`RLIMIT_NOFILE`)
+    OpenFiles,
+    /// Maximum number of processes of the real user of the process (`RLIMIT_NPROC`)
+    Processes,
+    /// Maximum size of a core dump file in bytes (`RLIMIT_CORE`)
+    CoreSize,
+}
+
+impl Resource {
+    fn as_raw(self) -> RawResource {
+        match self {
+            Self::AddressSpace => RawResource::RLIMIT_AS,
+            Self::CpuTime => RawResource::RLIMIT_CPU,
+            Self::OpenFiles => RawResource::RLIMIT_NOFILE,
+            Self::Processes => RawResource::RLIMIT_NPROC,
+            Self::CoreSize => RawResource::RLIMIT_CORE,
+        }
+    }
+}
+
+/// The network a zygote has access to, see [`ZygoteBuilder::network()`].
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Network {
+    /// Share the network of the calling process.
+    #[default]
+    Inherit,
+    /// No network at all. The zygote is created in a new network namespace
+    /// without any interface up, and connecting anywhere fails with `ENETUNREACH`.
+    None,
+    /// Only the loopback interface. The zygote is created in a new network
+    /// namespace where only `lo` is up, so that tasks can communicate over
+    /// local ports, but not with the outside world.
+    /// Note that the loopback interface is only shared with the processes
+    /// in the same namespace, e.g., those spawned from the zygote.
+    LoopbackOnly,
+}
+
+/// Builder to configure and create new zygote processes.
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use zygote::{DropPolicy, ZygoteBuilder};
+/// let zygote = ZygoteBuilder::new()
+///     .drop_policy(DropPolicy::Graceful(Duration::from_secs(1)))
+///     .build()
+///     .unwrap();
+/// let res = zygote.run(|x: u32| x * 2, 21);
+/// assert_eq!(res, 42);
+/// ```
+#[derive(Serialize, Deserialize, Default)]
+pub struct ZygoteBuilder {
+    pub(crate) drop_policy: DropPolicy,
+    pub(crate) kill_tree: bool,
+    /// `None` for the default, see [`ZygoteBuilder::parent_death_signal()`].
+    pub(crate) parent_death_signal: Option<Option<i32>>,
+    pub(crate) rlimits: Vec<(Resource, u64, u64)>,
+    pub(crate) privileges: Privileges,
+    pub(crate) broker: Option<Broker>,
+    pub(crate) user_namespace: bool,
+    pub(crate) mounts: Option<Mounts>,
+    pub(crate) network: Network,
+    pub(crate) cgroup: Option<Cgroup>,
+    #[cfg(feature = "seccomp")]
+    pub(crate) seccomp: Option<crate::SeccompPolicy>,
+    #[cfg(feature = "landlock")]
+    pub(crate) landlock: Option<crate::LandlockPolicy>,
+}
+
+impl ZygoteBuilder {
+    /// Create a new builder with the default configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set what happens to the zygote when its handle is dropped.
+    /// Defaults to [`DropPolicy::Kill`].
+    pub fn drop_policy(mut self, policy: DropPolicy) -> Self {
+        self.drop_policy = policy;
+        self
+    }
+
+    /// Terminate every process started from within the zygote, and not only
+    /// the zygote itself, when the zygote is killed or shut down.
+    /// Defaults to `false`.
+    ///
+    /// The zygote becomes a child subreaper (see `PR_SET_CHILD_SUBREAPER`
+    /// in [prctl(2)](https://man7.org/linux/man-pages/man2/prctl.2.html)), so that
+    /// orphaned processes are re-parented to it, and its descendants are killed
+    /// before the zygote is. The zygote stays in the PID namespace of its creator,
+    /// whatever its privileges, unless its mounts include a `/proc`
+    /// (see [`Mounts::proc()`](crate::Mounts::proc)), in which case the kernel
+    /// terminates the processes of the new namespace when the zygote exits.
+    ///
+    /// ```rust
+    /// # use zygote::Zygote;
+    /// let zygote = Zygote::builder().kill_tree(true).build().unwrap();
+    /// zygote.run(|_| {
+    ///     // this process would outlive the zygote without `kill_tree`
+    ///     std::process::Command::new("sleep").arg("1000").spawn().unwrap();
+    /// }, ());
+    /// drop(zygote); // kills `sleep`
+    /// ```
+    pub fn kill_tree(mut self, kill_tree: bool) -> Self {
+        self.kill_tree = kill_tree;
+        self
+    }
+
+    /// Set the signal the zygote receives when its parent process dies,
+    /// or `None` to let the zygote outlive its parent.
+    ///
+    /// Note that the signal is sent when the *thread* that created the zygote
+    /// exits (see `PR_SET_PDEATHSIG` in
+    /// [prctl(2)](https://man7.org/linux/man-pages/man2/prctl.2.html)), e.g.,
+    /// a zygote created from a worker thread is killed when that thread exits.
+    /// Hence this defaults to `Some(SIGKILL)` for zygotes created from the main
+    /// thread of their parent, and to `None` otherwise.
+    ///
+    /// The parent of a zygote created with [`Zygote::spawn()`] is the parent
+    /// of the zygote it was spawned from, and by default it gets the same
+    /// signal as that zygote.
+    ///
+    /// ```rust
+    /// # use zygote::Zygote;
+    /// let zygote = Zygote::builder()
+    ///     .parent_death_signal(Some(libc::SIGTERM))
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn parent_death_signal(mut self, signal: Option<i32>) -> Self {
+        self.parent_death_signal = Some(signal);
+        self
+    }
+
+    /// Limit the usage of `resource` by the zygote, with the given `soft`
+    /// and `hard` limits. Use [`libc::RLIM_INFINITY`] for no limit.
+    ///
+    /// Limits are applied to the zygote right after it's created, and they
+    /// are shared by all the tasks it runs. To override them for a single
+    /// task, use [`Zygote::try_run_with_rlimits()`].
+    ///
+    /// If the zygote dies while running a task, e.g., because it exceeded
+    /// its CPU time, the task fails with [`Error::Died`].
+    ///
+    /// ```rust
+    /// # use std::os::unix::process::ExitStatusExt;
+    /// # use zygote::{Error, Resource, Zygote};
+    /// let zygote = Zygote::builder()
+    ///     .rlimit(Resource::CpuTime, 1, 2)
+    ///     .build()
+    ///     .unwrap();
+    /// let res = zygote.try_run::<_, ()>(|_| loop {}, ());
+    /// let Err(Error::Died(status)) = res else { panic!() };
+    /// assert_eq!(status.signal(), Some(libc::SIGXCPU));
+    /// ```
+    pub fn rlimit(mut self, resource: Resource, soft: u64, hard: u64) -> Self {
+        self.rlimits.push((resource, soft, hard));
+        self
+    }
+
+    /// Run the zygote as the user `uid`.
+    /// The zygote loses all its capabilities, unless they are kept
+    /// with [`ZygoteBuilder::capabilities()`].
+    ///
+    /// Privileges are dropped in the zygote before it runs its first task.
+    /// If any of the changes fails, e.g., because the calling process lacks
+    /// the `CAP_SETUID` capability, creating the zygote fails.
+    ///
+    /// ```rust,no_run
+    /// # use zygote::{Capability, Zygote};
+    /// let zygote = Zygote::builder()
+    ///     .uid(65534)
+    ///     .gid(65534)
+    ///     .groups([])
+    ///     .capabilities([Capability::CAP_NET_BIND_SERVICE])
+    ///     .no_new_privs(true)
+    ///     .build()
+    ///     .unwrap();
+    /// let uid = zygote.run(|_| unsafe { libc::getuid() }, ());
+    /// assert_eq!(uid, 65534);
+    /// ```
+    pub fn uid(mut self, uid: u32) -> Self {
+        self.privileges.uid = Some(uid);
+        self
+    }
+
+    /// Run the zygote as the group `gid`. See [`ZygoteBuilder::uid()`].
+    pub fn gid(mut self, gid: u32) -> Self {
+        self.privileges.gid = Some(gid);
+        self
+    }
+
+    /// Set the supplementary groups of the zygote. See [`ZygoteBuilder::uid()`].
+    /// Defaults to no supplementary groups when the user or the group is
+    /// changed, and to the groups of the parent otherwise.
+    pub fn groups(mut self, groups: impl IntoIterator<Item = u32>) -> Self {
+        self.privileges.groups = Some(groups.into_iter().collect());
+        self
+    }
+
+    /// Limit the capabilities of the zygote to `capabilities`.
+    /// Any other capability is removed from the bounding, permitted,
+    /// effective and inheritable sets of the zygote.
+    /// See [capabilities(7)](https://man7.org/linux/man-pages/man7/capabilities.7.html)
+    /// and [`ZygoteBuilder::uid()`].
+    pub fn capabilities(mut self, capabilities: impl IntoIterator<Item = Capability>) -> Self {
+        self.privileges.capabilities = Some(capabilities.into_iter().collect());
+        self
+    }
+
+    /// Raise `capabilities` in the ambient set of the zygote, so that they
+    /// are preserved by the programs it executes. They must also be part of
+    /// the set passed to [`ZygoteBuilder::capabilities()`].
+    pub fn ambient_capabilities(
+        mut self,
+        capabilities: impl IntoIterator<Item = Capability>,
+    ) -> Self {
+        self.privileges.ambient_capabilities = capabilities.into_iter().collect();
+        self
+    }
+
+    /// Set the securebits flags of the zygote, e.g., `SECBIT_NOROOT`.
+    /// See [capabilities(7)](https://man7.org/linux/man-pages/man7/capabilities.7.html)
+    /// and [`ZygoteBuilder::uid()`].
+    pub fn securebits(mut self, securebits: u32) -> Self {
+        self.privileges.securebits = Some(securebits);
+        self
+    }
+
+    /// Set the `no_new_privs` flag of the zygote, so that neither it nor the
+    /// programs it executes can gain new privileges, e.g., through setuid binaries.
+    /// See `PR_SET_NO_NEW_PRIVS` in [prctl(2)](https://man7.org/linux/man-pages/man2/prctl.2.html).
+    /// Defaults to `false`.
+    pub fn no_new_privs(mut self, no_new_privs: bool) -> Self {
+        self.privileges.no_new_privs = no_new_privs;
+        self
+    }
+
+    /// Set the [`Broker`] the tasks of the zygote can obtain with
+    /// [`Broker::current()`] to open files on their behalf.
+    /// Defaults to the broker of the zygote it's spawned from, if any.
+    pub fn broker(mut self, broker: Broker) -> Self {
+        self.broker = Some(broker);
+        self
+    }
+
+    /// Create the zygote in a new user namespace. Defaults to `false`.
+    ///
+    /// Inside the namespace, the zygote keeps the effective user and group ids
+    /// of the calling process, and it has all the capabilities, e.g., to set up
+    /// its mounts (see [`ZygoteBuilder::mounts()`]). Unprivileged processes
+    /// can use the other namespace options this way.
+    /// See [user_namespaces(7)](https://man7.org/linux/man-pages/man7/user_namespaces.7.html).
+    ///
+    /// ```rust
+    /// # use zygote::Zygote;
+    /// # let Ok(zygote) = Zygote::builder().user_namespace(true).build() else { return };
+    /// let uid = zygote.run(|_| unsafe { libc::getuid() }, ());
+    /// assert_eq!(uid, unsafe { libc::getuid() });
+    /// ```
+    pub fn user_namespace(mut self, user_namespace: bool) -> Self {
+        self.user_namespace = user_namespace;
+        self
+    }
+
+    /// Create the zygote in a new mount namespace, with the root filesystem
+    /// described by `mounts`. Unless the calling process has the
+    /// `CAP_SYS_ADMIN` capability, this requires a user namespace, see
+    /// [`ZygoteBuilder::user_namespace()`].
+    ///
+    /// The root filesystem is set up with `pivot_root` before the zygote runs
+    /// its first task. See [`Mounts`].
+    pub fn mounts(mut self, mounts: Mounts) -> Self {
+        self.mounts = Some(mounts);
+        self
+    }
+
+    /// Set the network the zygote has access to. Defaults to [`Network::Inherit`].
+    ///
+    /// Any other option creates the zygote in a new network namespace.
+    /// Unless the calling process has the `CAP_SYS_ADMIN` capability, this
+    /// requires a user namespace, see [`ZygoteBuilder::user_namespace()`].
+    ///
+    /// ```rust
+    /// # use zygote::{Network, WireError, Zygote};
+    /// # if Zygote::builder().user_namespace(true).build().is_err() { return }
+    /// let zygote = Zygote::builder()
+    ///     .user_namespace(true)
+    ///     .network(Network::None)
+    ///     .build()
+    ///     .unwrap();
+    /// let res = zygote.run(|_| -> Result<(), WireError> {
+    ///     std::net::TcpStream::connect("1.1.1.1:80")?;
+    ///     Ok(())
+    /// }, ());
+    /// assert_eq!(res.unwrap_err().raw_os_error(), Some(libc::ENETUNREACH));
+    /// ```
+    pub fn network(mut self, network: Network) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// Create the zygote inside the cgroup v2 group `cgroup`, so that it's
+    /// subject to the limits of the group from the start. See [`Cgroup`].
+    ///
+    /// The zygote is created with `CLONE_INTO_CGROUP` (see
+    /// [clone(2)](https://man7.org/linux/man-pages/man2/clone.2.html)) if possible,
+    /// or moved into the group right after it's created otherwise.
+    /// Processes spawned from the zygote are created in the same group.
+    pub fn cgroup(mut self, cgroup: Cgroup) -> Self {
+        self.cgroup = Some(cgroup);
+        self
+    }
+
+    /// Restrict the system calls the zygote can make with a seccomp `policy`.
+    /// The policy is installed in the zygote before it runs its first task,
+    /// and it's inherited by any zygote or process spawned from it.
+    /// See [`SeccompPolicy`](crate::SeccompPolicy).
+    #[cfg(feature = "seccomp")]
+    pub fn seccomp(mut self, policy: crate::SeccompPolicy) -> Self {
+        self.seccomp = Some(policy);
+        self
+    }
+
+    /// Restrict the filesystem access of the zygote with a Landlock `policy`.
+    /// The policy is installed in the zygote before it runs its first task,
+    /// and it's inherited by any zygote or process spawned from it.
+    /// See [`LandlockPolicy`](crate::LandlockPolicy).
+    #[cfg(feature = "landlock")]
+    pub fn landlock(mut self, policy: crate::LandlockPolicy) -> Self {
+        self.landlock = Some(policy);
+        self
+    }
+
+    /// Create a new zygote process as a child of the calling process.
+    /// See [`Zygote::new()`].
+    pub fn build(&self) -> Result<Zygote, Error> {
+        let zygote = Zygote::new_impl(Relation::Child, self)?;
+        observer::notify(|o| o.zygote_created(&zygote));
+        Ok(zygote)
+    }
+
+    /// Create a new zygote process from within `zygote`, as a sibling of it.
+    /// See [`Zygote::spawn()`].
+    #[track_caller]
+    pub fn spawn(&self, zygote: &Zygote) -> Result<Zygote, Error> {
+        let spawned = zygote.try_run(spawner, self)??.into_zygote();
+        observer::notify(|o| o.zygote_spawned(zygote, &spawned));
+        Ok(spawned)
+    }
+
+    /// Create a new zygote process from within `zygote`, as a child of it.
+    /// See [`Zygote::spawn_child()`].
+    #[track_caller]
+    pub fn spawn_child(&self, zygote: &Zygote) -> Result<Zygote, Error> {
+        let spawned = zygote.try_run(child_spawner, self)??.into_zygote();
+        observer::notify(|o| o.zygote_spawned(zygote, &spawned));
+        Ok(spawned)
+    }
+}
+
+impl ZygoteBuilder {
+    /// The namespaces the zygote is created in, as `clone` flags.
+    pub(crate) fn namespaces(&self) -> i32 {
+        let mut flags = 0;
+        if self.user_namespace {
+            flags |= libc::CLONE_NEWUSER;
+        }
+        if self.network != Network::Inherit {
+            flags |= libc::CLONE_NEWNET;
+        }
+        if let Some(mounts) = &self.mounts {
+            flags |= libc::CLONE_NEWNS;
+            if mounts.has_proc() {
+                flags |= libc::CLONE_NEWPID;
+            }
+        }
+        flags
+    }
+
+    /// Apply the configuration to the calling process, the new zygote.
+    /// `ids` are the ids of its creator, and `parent` the process it dies with.
+    pub(crate) fn setup(&self, ids: Ids, parent: Option<&(Process, i32)>) -> Result<(), WireError> {
+        if self.user_namespace {
+            ids.map()?;
+        }
+        if self.network == Network::LoopbackOnly {
+            namespace::loopback_up()?;
+        }
+        if let Some(mounts) = &self.mounts {
+            mounts.apply()?;
+        }
+        for &(resource, soft, hard) in &self.rlimits {
+            setrlimit(resource.as_raw(), soft, hard).map_err(|err| {
+                WireError::from(err).context(format_args!("set {resource:?} limit"))
+            })?;
+        }
+        self.privileges.apply()?;
+        // switching users or capabilities clears the parent death signal
+        if let Some((parent, signal)) = parent {
+            process::die_with_parent(parent, *signal);
+        }
+        #[cfg(feature = "landlock")]
+        if let Some(policy) = &self.landlock {
+            policy.install()?;
+        }
+        // seccomp goes last, as it could forbid the syscalls above
+        #[cfg(feature = "seccomp")]
+        if let Some(policy) = &self.seccomp {
+            policy.install()?;
+        }
+        Ok(())
+    }
+}
+
+fn spawner(builder: ZygoteBuilder) -> Result<Transfer, WireError> {
+    Ok(Zygote::new_impl(Relation::Sibling, &builder)?.transfer())
+}
+
+fn child_spawner(builder: ZygoteBuilder) -> Result<Transfer, WireError> {
+    let zygote = Zygote::new_impl(Relation::Nested, &builder)?;
+    zygote.track();
+    Ok(zygote.transfer())
+}
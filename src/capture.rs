@@ -0,0 +1,96 @@
+use std::fs::File;
+use std::io::{self, Read as _, Seek as _, Write as _};
+use std::os::fd::{AsRawFd as _, FromRawFd as _, OwnedFd, RawFd};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use serde::{Deserialize, Serialize};
+
+use crate::WireError;
+
+/// The output a task wrote to its standard output and error,
+/// see [`Zygote::run_captured()`](crate::Zygote::run_captured).
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Output {
+    /// What the task wrote to its standard output.
+    pub stdout: Vec<u8>,
+    /// What the task wrote to its standard error.
+    pub stderr: Vec<u8>,
+}
+
+/// Run `f`, capturing what it writes to the standard output and error
+/// of the calling process. If `f` panics, the output, including the panic
+/// message, becomes the source of the error.
+pub(crate) fn capture<R>(f: impl FnOnce() -> R) -> Result<(R, Output), WireError> {
+    let fail = |err| WireError::from(err).context("capture the output");
+    let stdout = memfd(c"zygote-stdout").map_err(fail)?;
+    let stderr = memfd(c"zygote-stderr").map_err(fail)?;
+    let redirect = Redirect::new([(1, &stdout), (2, &stderr)]).map_err(fail)?;
+    let ret = catch_unwind(AssertUnwindSafe(f));
+    // restore the streams before reading what was written to them
+    drop(redirect);
+    let output = Output {
+        stdout: read(stdout).map_err(fail)?,
+        stderr: read(stderr).map_err(fail)?,
+    };
+    match ret {
+        Ok(ret) => Ok((ret, output)),
+        Err(_) => Err(crate::take_panic().with_source(output.to_error())),
+    }
+}
+
+impl Output {
+    fn to_error(&self) -> WireError {
+        WireError::from_str(format!(
+            "the task wrote {:?} to its standard output and {:?} to its standard error",
+            String::from_utf8_lossy(&self.stdout),
+            String::from_utf8_lossy(&self.stderr),
+        ))
+    }
+}
+
+/// Redirects file descriptors while alive, restoring them on drop,
+/// even if the task panics.
+struct Redirect(Vec<(RawFd, OwnedFd)>);
+
+impl Redirect {
+    fn new<const N: usize>(fds: [(RawFd, &File); N]) -> io::Result<Self> {
+        // anything buffered so far doesn't belong to the task
+        let _ = io::stdout().flush();
+        let mut redirect = Redirect(vec![]);
+        for (target, file) in fds {
+            let saved = unsafe { libc::fcntl(target, libc::F_DUPFD_CLOEXEC, 3) };
+            if saved == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            let saved = unsafe { OwnedFd::from_raw_fd(saved) };
+            if unsafe { libc::dup2(file.as_raw_fd(), target) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            redirect.0.push((target, saved));
+        }
+        Ok(redirect)
+    }
+}
+
+impl Drop for Redirect {
+    fn drop(&mut self) {
+        let _ = io::stdout().flush();
+        for (target, saved) in self.0.drain(..) {
+            unsafe { libc::dup2(saved.as_raw_fd(), target) };
+        }
+    }
+}
+
+fn memfd(name: &std::ffi::CStr) -> io::Result<File> {
+    match unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC) } {
+        -1 => Err(io::Error::last_os_error()),
+        fd => Ok(unsafe { File::from_raw_fd(fd) }),
+    }
+}
+
+fn read(mut file: File) -> io::Result<Vec<u8>> {
+    let mut content = vec![];
+    file.rewind()?;
+    file.read_to_end(&mut content)?;
+    Ok(content)
+}
@@ -0,0 +1,145 @@
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{self, Read as _, Write as _};
+use std::os::fd::{AsFd, AsRawFd as _, BorrowedFd, FromRawFd as _, OwnedFd};
+use std::os::unix::ffi::OsStrExt as _;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, WireFd};
+
+/// A cgroup v2 group that zygotes can be created in,
+/// see [`ZygoteBuilder::cgroup()`](crate::ZygoteBuilder::cgroup).
+///
+/// The group must be delegated to the calling process, i.e., the process
+/// must be allowed to write its `cgroup.procs` file, as well as the one of
+/// the group the calling process is in. The limits can only be set if the
+/// corresponding controllers are enabled for the group, see
+/// [cgroups(7)](https://man7.org/linux/man-pages/man7/cgroups.7.html).
+///
+/// ```rust,no_run
+/// # use zygote::{Cgroup, Zygote};
+/// let cgroup = Cgroup::create("/sys/fs/cgroup/my-service/zygote").unwrap();
+/// cgroup.set_memory_max(Some(64 << 20)).unwrap();
+/// cgroup.set_pids_max(Some(16)).unwrap();
+///
+/// let zygote = Zygote::builder().cgroup(cgroup.clone()).build().unwrap();
+/// zygote.run(|_| vec![0u8; 1 << 20], ());
+/// assert!(cgroup.memory_current().unwrap() > 0);
+/// ```
+#[derive(Serialize, Deserialize)]
+pub struct Cgroup(WireFd<Arc<OwnedFd>>);
+
+impl Cgroup {
+    /// Open the existing group at `path`, e.g., `/sys/fs/cgroup/my-service`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Cgroup, Error> {
+        let dir = File::open(path)?;
+        Ok(Cgroup(WireFd::new(Arc::new(dir.into()))))
+    }
+
+    /// Create a new group at `path`, or open it if it already exists.
+    pub fn create(path: impl AsRef<Path>) -> Result<Cgroup, Error> {
+        match std::fs::create_dir(path.as_ref()) {
+            Err(err) if err.kind() != io::ErrorKind::AlreadyExists => Err(err.into()),
+            _ => Self::open(path),
+        }
+    }
+
+    /// Limit the memory usage of the group to `bytes` (`memory.max`),
+    /// or remove the limit with `None`.
+    pub fn set_memory_max(&self, bytes: Option<u64>) -> Result<(), Error> {
+        self.write("memory.max", &max(bytes))
+    }
+
+    /// Limit the number of processes in the group to `pids` (`pids.max`),
+    /// or remove the limit with `None`.
+    pub fn set_pids_max(&self, pids: Option<u64>) -> Result<(), Error> {
+        self.write("pids.max", &max(pids))
+    }
+
+    /// Limit the group to use the CPU for `quota` every `period` (`cpu.max`),
+    /// or remove the limit with `None`.
+    /// For example, a quota of 50ms every 100ms limits the group to half a CPU.
+    pub fn set_cpu_max(&self, quota: Option<Duration>, period: Duration) -> Result<(), Error> {
+        let quota = max(quota.map(|q| q.as_micros() as u64));
+        self.write("cpu.max", &format!("{quota} {}", period.as_micros()))
+    }
+
+    /// Get the memory currently used by the group in bytes (`memory.current`).
+    pub fn memory_current(&self) -> Result<u64, Error> {
+        parse(&self.read("memory.current")?)
+    }
+
+    /// Get the number of processes currently in the group (`pids.current`).
+    pub fn pids_current(&self) -> Result<u64, Error> {
+        parse(&self.read("pids.current")?)
+    }
+
+    /// Get the CPU time used by the group so far (`usage_usec` in `cpu.stat`).
+    pub fn cpu_usage(&self) -> Result<Duration, Error> {
+        let stat = self.read("cpu.stat")?;
+        let usage = stat
+            .lines()
+            .find_map(|line| line.strip_prefix("usage_usec "))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing usage_usec"))?;
+        Ok(Duration::from_micros(parse(usage)?))
+    }
+
+    /// Get the processes in the group (`cgroup.procs`).
+    pub fn procs(&self) -> Result<Vec<u32>, Error> {
+        self.read("cgroup.procs")?.lines().map(parse).collect()
+    }
+
+    fn read(&self, name: &str) -> Result<String, Error> {
+        let mut content = String::new();
+        open_at(self.as_fd(), name, libc::O_RDONLY)?.read_to_string(&mut content)?;
+        Ok(content)
+    }
+
+    fn write(&self, name: &str, value: &str) -> Result<(), Error> {
+        open_at(self.as_fd(), name, libc::O_WRONLY)?.write_all(value.as_bytes())?;
+        Ok(())
+    }
+}
+
+impl AsFd for Cgroup {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl Clone for Cgroup {
+    fn clone(&self) -> Self {
+        Cgroup(WireFd::new(Arc::clone(&self.0)))
+    }
+}
+
+/// Move the process `pid` into the group `cgroup`.
+pub(crate) fn attach(cgroup: BorrowedFd, pid: libc::pid_t) -> io::Result<()> {
+    let mut procs = open_at(cgroup, "cgroup.procs", libc::O_WRONLY)?;
+    procs.write_all(pid.to_string().as_bytes())
+}
+
+fn max(value: Option<u64>) -> String {
+    value.map_or("max".into(), |v| v.to_string())
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> Result<T, Error> {
+    let value = value.trim();
+    value.parse().map_err(|_| {
+        let msg = format!("invalid value {value:?}");
+        Error::Io(io::Error::new(io::ErrorKind::InvalidData, msg))
+    })
+}
+
+fn open_at(dir: BorrowedFd, name: impl AsRef<Path>, flags: i32) -> io::Result<File> {
+    let name = CString::new(name.as_ref().as_os_str().as_bytes())?;
+    let fd = unsafe { libc::openat(dir.as_raw_fd(), name.as_ptr(), flags | libc::O_CLOEXEC) };
+    match fd {
+        -1 => Err(io::Error::last_os_error()),
+        fd => Ok(unsafe { File::from_raw_fd(fd) }),
+    }
+}
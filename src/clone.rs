@@ -0,0 +1,183 @@
+use std::io;
+use std::os::fd::{BorrowedFd, RawFd};
+
+use libc::{CLONE_PIDFD, SIGCHLD, SIGKILL};
+
+use crate::cgroup;
+use crate::process::Process;
+
+/// Clone the calling process, returning the child in the parent.
+/// The child is created in the `cgroup` group, if any.
+pub(crate) fn clone3_or_clone(
+    flags: i32,
+    exit_signal: i32,
+    cgroup: Option<BorrowedFd>,
+) -> io::Result<Option<Process>> {
+    #[cfg(feature = "clone3")]
+    if let Ok(res) = clone3(flags, exit_signal, cgroup) {
+        return Ok(res);
+    }
+    let child = clone(flags, exit_signal)?;
+    if let (Some(child), Some(cgroup)) = (&child, cgroup) {
+        // the child doesn't do anything before we hear back from it,
+        // so we can move it before it runs any task
+        if let Err(err) = cgroup::attach(cgroup, child.pid()?) {
+            kill(child);
+            return Err(err);
+        }
+    }
+    Ok(child)
+}
+
+#[cfg(all(test, feature = "clone3"))]
+thread_local! {
+    /// Make `clone3` fail with `ENOSYS`, as if the kernel didn't support it.
+    static NO_CLONE3: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+#[cfg(feature = "clone3")]
+fn clone3(flags: i32, exit_signal: i32, cgroup: Option<BorrowedFd>) -> io::Result<Option<Process>> {
+    use std::os::fd::AsRawFd as _;
+
+    #[cfg(test)]
+    if NO_CLONE3.get() {
+        return Err(io::Error::from_raw_os_error(libc::ENOSYS));
+    }
+    let mut pidfd: RawFd = -1;
+    let mut flags = (flags | CLONE_PIDFD) as u64;
+    if cgroup.is_some() {
+        flags |= CLONE_INTO_CGROUP;
+    }
+    let cgroup = cgroup.map_or(0, |fd| fd.as_raw_fd() as u64);
+    let pidfd_ptr = std::ptr::from_mut(&mut pidfd) as u64;
+    let mut args = [
+        flags,
+        pidfd_ptr,
+        0,
+        0,
+        exit_signal as u64,
+        0,
+        0,
+        0,
+        0,
+        0,
+        cgroup,
+    ];
+    let args_ptr = std::ptr::from_mut(&mut args);
+    let args_size = std::mem::size_of_val(&args);
+    let res = unsafe { libc::syscall(libc::SYS_clone3, args_ptr, args_size) };
+    match res {
+        0 => Ok(None),
+        pid @ 1.. => Ok(Some(Process::from_raw(pidfd, pid as _))),
+        -1 => Err(io::Error::last_os_error()),
+        _ => Err(io::Error::other("unknown")),
+    }
+}
+
+#[cfg(feature = "clone3")]
+const CLONE_INTO_CGROUP: u64 = 0x200000000;
+
+/// Clone the calling process with the legacy interfaces.
+fn clone(flags: i32, exit_signal: i32) -> io::Result<Option<Process>> {
+    // a plain fork also runs the libc atfork handlers, prefer it when we can
+    if flags == 0 && exit_signal == SIGCHLD {
+        return fork();
+    }
+    // with a null stack the child keeps using a copy of the parent's stack,
+    // and returns from the syscall just like fork does.
+    // kernels before 5.2 ignore CLONE_PIDFD, and leave the pidfd untouched
+    let mut pidfd: RawFd = -1;
+    let flags = (flags | CLONE_PIDFD | exit_signal) as libc::c_ulong;
+    let stack = std::ptr::null_mut::<libc::c_void>();
+    let pidfd_ptr = std::ptr::from_mut(&mut pidfd);
+    // the parent tid pointer is the third argument everywhere, but
+    // s390 swaps the flags and the stack
+    #[cfg(not(target_arch = "s390x"))]
+    let res = unsafe { libc::syscall(libc::SYS_clone, flags, stack, pidfd_ptr, 0, 0) };
+    #[cfg(target_arch = "s390x")]
+    let res = unsafe { libc::syscall(libc::SYS_clone, stack, flags, pidfd_ptr, 0, 0) };
+    match res {
+        0 => Ok(None),
+        pid @ 1.. => Ok(Some(Process::from_raw(pidfd, pid as _))),
+        -1 => Err(io::Error::last_os_error()),
+        _ => Err(io::Error::other("unknown")),
+    }
+}
+
+fn fork() -> io::Result<Option<Process>> {
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(None),
+        pid => match Process::open(pid) {
+            Ok(child) => Ok(Some(child)),
+            Err(err) => {
+                kill(&Process::Pid(pid));
+                Err(err)
+            }
+        },
+    }
+}
+
+fn kill(child: &Process) {
+    let _ = child.send_signal(SIGKILL);
+    let _ = child.wait();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::Zygote;
+
+    fn no_clone3() {
+        #[cfg(feature = "clone3")]
+        NO_CLONE3.set(true);
+    }
+
+    fn exit(child: Option<Process>, code: i32) -> Process {
+        match child {
+            Some(child) => child,
+            None => unsafe { libc::_exit(code) },
+        }
+    }
+
+    #[test]
+    fn fork_fallback() {
+        no_clone3();
+        let child = exit(clone3_or_clone(0, SIGCHLD, None).unwrap(), 3);
+        assert_eq!(child.wait().unwrap().unwrap().code(), Some(3));
+    }
+
+    #[test]
+    fn clone_fallback() {
+        no_clone3();
+        // a child that doesn't signal its parent can't be forked
+        let child = exit(clone3_or_clone(0, 0, None).unwrap(), 4);
+        assert_eq!(child.wait().unwrap().unwrap().code(), Some(4));
+
+        // creating a pid namespace needs privileges
+        if let Ok(child) = clone3_or_clone(libc::CLONE_NEWPID, SIGCHLD, None) {
+            let child = exit(child, 5);
+            assert_eq!(child.wait().unwrap().unwrap().code(), Some(5));
+        }
+    }
+
+    #[test]
+    fn zygote_fallback() {
+        no_clone3();
+        let zygote = Zygote::new();
+        assert_ne!(zygote.run(|_| std::process::id(), ()), std::process::id());
+
+        // the thread local carries over to the zygote
+        let nested = zygote.spawn_child();
+        let sibling = zygote.spawn();
+        assert_eq!(nested.run(|x| x + 1, 1), 2);
+        assert_eq!(sibling.run(|x| x + 1, 2), 3);
+        drop(nested);
+        drop(sibling);
+
+        let status = zygote.shutdown(Duration::from_secs(5)).unwrap();
+        assert!(status.success());
+    }
+}
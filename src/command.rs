@@ -0,0 +1,1011 @@
+use std::ffi::{CString, OsStr, OsString};
+use std::fs::File;
+use std::io::{self, Read as _};
+use std::os::fd::{AsFd as _, AsRawFd as _, FromRawFd as _, OwnedFd, RawFd};
+use std::os::unix::ffi::{OsStrExt as _, OsStringExt as _};
+use std::os::unix::process::ExitStatusExt as _;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::sync::{Arc, Mutex};
+
+use libc::{CLONE_PIDFD, CLONE_VFORK, CLONE_VM, SIGCHLD, SIGKILL};
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::sys::socket::{recv, send, socketpair, AddressFamily, MsgFlags, SockFlag, SockType};
+use serde::{Deserialize, Serialize};
+
+use crate::process::Process;
+use crate::{Error, WireError, WireFd, Zygote};
+
+/// A command to execute from within a zygote, similar to [`std::process::Command`].
+///
+/// The command is started by the zygote with `CLONE_VM | CLONE_VFORK`, like
+/// `posix_spawn` does, so starting it doesn't copy the memory of the zygote,
+/// however large it is. The command starts with an empty signal mask, and with
+/// the default disposition for every signal the zygote handles.
+///
+/// ```rust
+/// # use zygote::{Command, Zygote};
+/// let zygote = Zygote::new();
+/// let mut child = Command::new("sh")
+///     .args(["-c", "exit 3"])
+///     .spawn(&zygote)
+///     .unwrap();
+/// assert_eq!(child.wait().unwrap().code(), Some(3));
+/// ```
+#[derive(Serialize, Deserialize)]
+pub struct Command {
+    program: OsString,
+    args: Vec<OsString>,
+    env_clear: bool,
+    env: Vec<(OsString, Option<OsString>)>,
+    current_dir: Option<PathBuf>,
+    stdio: [Stdio; 3],
+    fds: Vec<(RawFd, WireFd<Arc<OwnedFd>>)>,
+    pty: Option<(u16, u16)>,
+    session: bool,
+    process_group: Option<libc::pid_t>,
+    foreground: bool,
+    parent_death_signal: Option<i32>,
+}
+
+/// What to connect a standard stream of a [`Command`] to.
+#[derive(Serialize, Deserialize, Default)]
+pub enum Stdio {
+    /// Inherit the stream from the zygote.
+    #[default]
+    Inherit,
+    /// Connect the stream to `/dev/null`, as opened by the zygote.
+    Null,
+    /// Connect the stream to a new pipe, see [`Child::stdin`].
+    Piped,
+    /// Connect the stream to the given file descriptor.
+    Fd(WireFd<Arc<OwnedFd>>),
+}
+
+impl Stdio {
+    /// Inherit the stream from the zygote.
+    pub fn inherit() -> Self {
+        Stdio::Inherit
+    }
+
+    /// Connect the stream to `/dev/null`.
+    pub fn null() -> Self {
+        Stdio::Null
+    }
+
+    /// Connect the stream to a new pipe.
+    pub fn piped() -> Self {
+        Stdio::Piped
+    }
+}
+
+impl From<OwnedFd> for Stdio {
+    fn from(fd: OwnedFd) -> Self {
+        Stdio::Fd(WireFd::new(Arc::new(fd)))
+    }
+}
+
+impl From<File> for Stdio {
+    fn from(file: File) -> Self {
+        Stdio::from(OwnedFd::from(file))
+    }
+}
+
+impl<T: Into<OwnedFd>> From<WireFd<T>> for Stdio {
+    fn from(fd: WireFd<T>) -> Self {
+        Stdio::from(fd.into_inner().into())
+    }
+}
+
+impl Command {
+    /// Create a command to execute `program`.
+    /// If `program` is not a path, it is searched for in the `PATH`
+    /// of the command.
+    pub fn new(program: impl AsRef<OsStr>) -> Self {
+        Self {
+            program: program.as_ref().to_owned(),
+            args: vec![],
+            env_clear: false,
+            env: vec![],
+            current_dir: None,
+            stdio: Default::default(),
+            fds: vec![],
+            pty: None,
+            session: false,
+            process_group: None,
+            foreground: false,
+            parent_death_signal: None,
+        }
+    }
+
+    /// Add an argument to pass to the program.
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Self {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    /// Add multiple arguments to pass to the program.
+    pub fn args(mut self, args: impl IntoIterator<Item = impl AsRef<OsStr>>) -> Self {
+        self.args
+            .extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
+        self
+    }
+
+    /// Set the environment variable `key` to `value`.
+    pub fn env(mut self, key: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> Self {
+        let value = Some(value.as_ref().to_owned());
+        self.env.push((key.as_ref().to_owned(), value));
+        self
+    }
+
+    /// Remove the environment variable `key`.
+    pub fn env_remove(mut self, key: impl AsRef<OsStr>) -> Self {
+        self.env.push((key.as_ref().to_owned(), None));
+        self
+    }
+
+    /// Don't inherit the environment of the zygote, only pass the variables
+    /// set with [`Command::env()`].
+    pub fn env_clear(mut self) -> Self {
+        self.env_clear = true;
+        self.env.clear();
+        self
+    }
+
+    /// Set the working directory of the command.
+    pub fn current_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    /// Set what the standard input of the command is connected to.
+    /// Defaults to [`Stdio::Inherit`].
+    pub fn stdin(mut self, stdio: impl Into<Stdio>) -> Self {
+        self.stdio[0] = stdio.into();
+        self
+    }
+
+    /// Set what the standard output of the command is connected to.
+    /// Defaults to [`Stdio::Inherit`].
+    pub fn stdout(mut self, stdio: impl Into<Stdio>) -> Self {
+        self.stdio[1] = stdio.into();
+        self
+    }
+
+    /// Set what the standard error of the command is connected to.
+    /// Defaults to [`Stdio::Inherit`].
+    pub fn stderr(mut self, stdio: impl Into<Stdio>) -> Self {
+        self.stdio[2] = stdio.into();
+        self
+    }
+
+    /// Make `fd` available to the command as the file descriptor `target`,
+    /// e.g., for socket activation or to pass a jobserver pipe.
+    /// A mapping for a standard stream takes precedence over its [`Stdio`].
+    ///
+    /// Any other file descriptor is closed in the command, besides
+    /// the standard streams.
+    ///
+    /// ```rust
+    /// # use std::io::read_to_string;
+    /// # use std::os::unix::net::UnixStream;
+    /// # use zygote::{Command, Zygote};
+    /// let zygote = Zygote::new();
+    /// let (reader, writer) = UnixStream::pair().unwrap();
+    /// let mut child = Command::new("sh")
+    ///     .args(["-c", "echo hello >&3"])
+    ///     .fd(3, writer)
+    ///     .spawn(&zygote)
+    ///     .unwrap();
+    /// child.wait().unwrap();
+    /// assert_eq!(read_to_string(reader).unwrap(), "hello\n");
+    /// ```
+    pub fn fd(mut self, target: RawFd, fd: impl Into<OwnedFd>) -> Self {
+        self.fds.push((target, WireFd::new(Arc::new(fd.into()))));
+        self
+    }
+
+    /// Make the command the leader of a new session, and of a new process
+    /// group within it, detaching it from the terminal of the zygote.
+    /// This is implied by [`Command::pty()`].
+    pub fn setsid(mut self, setsid: bool) -> Self {
+        self.session = setsid;
+        self
+    }
+
+    /// Put the command in the process group `pgid`, or in a new process group
+    /// led by the command if `pgid` is 0. The pid of a group is seen from the
+    /// zygote, e.g., the [`Child::id()`] of the first command of a pipeline.
+    ///
+    /// ```rust
+    /// # use zygote::{Command, Zygote};
+    /// let zygote = Zygote::new();
+    /// let mut first = Command::new("sleep").arg("1000").process_group(0).spawn(&zygote).unwrap();
+    /// let mut second = Command::new("sleep")
+    ///     .arg("1000")
+    ///     .process_group(first.id() as _)
+    ///     .spawn(&zygote)
+    ///     .unwrap();
+    ///
+    /// // kill the whole pipeline
+    /// first.signal_group(libc::SIGKILL).unwrap();
+    /// assert!(!first.wait().unwrap().success());
+    /// assert!(!second.wait().unwrap().success());
+    /// ```
+    pub fn process_group(mut self, pgid: libc::pid_t) -> Self {
+        self.process_group = Some(pgid);
+        self
+    }
+
+    /// Make the process group of the command the foreground process group
+    /// of its controlling terminal, i.e., the one of the zygote, or the one
+    /// from [`Command::pty()`].
+    /// Use it along with [`Command::process_group()`] for job control.
+    pub fn foreground(mut self, foreground: bool) -> Self {
+        self.foreground = foreground;
+        self
+    }
+
+    /// Run the command in a new pseudo-terminal of `rows` by `cols` characters.
+    ///
+    /// The command becomes the leader of a new session, with the terminal as
+    /// its controlling terminal, and any standard stream left as
+    /// [`Stdio::Inherit`] is connected to it. The other end of the terminal
+    /// is returned in [`Child::pty`].
+    ///
+    /// ```rust
+    /// # use std::io::Read as _;
+    /// # use zygote::{Command, Zygote};
+    /// let zygote = Zygote::new();
+    /// let mut child = Command::new("sh")
+    ///     .args(["-c", "[ -t 0 ] && stty size"])
+    ///     .pty(24, 80)
+    ///     .spawn(&zygote)
+    ///     .unwrap();
+    /// assert!(child.wait().unwrap().success());
+    ///
+    /// let mut output = [0u8; 7];
+    /// child.pty.unwrap().read_exact(&mut output).unwrap();
+    /// assert_eq!(&output, b"24 80\r\n");
+    /// ```
+    pub fn pty(mut self, rows: u16, cols: u16) -> Self {
+        self.pty = Some((rows, cols));
+        self
+    }
+
+    /// Set the signal the command receives when the zygote dies,
+    /// or `None` to let the command outlive the zygote.
+    /// Defaults to `None`.
+    pub fn parent_death_signal(mut self, signal: Option<i32>) -> Self {
+        self.parent_death_signal = signal;
+        self
+    }
+
+    /// Start the command from within `zygote`.
+    ///
+    /// The file descriptors of the command are sent to the zygote along with it.
+    /// The command is a child of the zygote, which reaps it when it exits.
+    /// If the program can't be executed, this method returns the error
+    /// that prevented it, e.g., `ENOENT` if the program doesn't exist.
+    #[track_caller]
+    pub fn spawn(&self, zygote: &Zygote) -> Result<Child, Error> {
+        Ok(zygote.try_run(spawn, self)??)
+    }
+}
+
+/// A command started from within a zygote, see [`Command::spawn()`].
+///
+/// Since the command is a child of the zygote, the zygote reports its exit
+/// status once it reaps it. This happens while the zygote is waiting for
+/// tasks, so a zygote busy running a long task delays the report. The same
+/// goes for signals on kernels without pidfds (before 5.3), and for signals
+/// to process groups before 6.9, as they're sent from the zygote, the only
+/// process that knows the pid of the command for sure.
+#[derive(Serialize, Deserialize)]
+pub struct Child {
+    /// The writing end of the standard input of the command,
+    /// if it is [`Stdio::Piped`].
+    pub stdin: Option<WireFd<File>>,
+    /// The reading end of the standard output of the command,
+    /// if it is [`Stdio::Piped`].
+    pub stdout: Option<WireFd<File>>,
+    /// The reading end of the standard error of the command,
+    /// if it is [`Stdio::Piped`].
+    pub stderr: Option<WireFd<File>>,
+    /// The controlling end of the pseudo-terminal of the command,
+    /// if it runs in one, see [`Command::pty()`].
+    pub pty: Option<WireFd<File>>,
+    process: Process,
+    pid: u32,
+    pgid: Option<libc::pid_t>,
+    status: WireFd<File>,
+    /// The socket to ask the zygote to signal the command, see [`serve_signal()`].
+    signals: WireFd<OwnedFd>,
+    #[serde(skip)]
+    signals_lock: Mutex<()>,
+    #[serde(skip)]
+    exit_status: Option<ExitStatus>,
+}
+
+impl Child {
+    /// Get the pid of the command, as seen from the zygote.
+    pub fn id(&self) -> u32 {
+        self.pid
+    }
+
+    /// Send `signal` to the command.
+    pub fn send_signal(&self, signal: i32) -> Result<(), Error> {
+        if self.exit_status.is_some() {
+            return Err(io::Error::from_raw_os_error(libc::ESRCH).into());
+        }
+        match self.process.pidfd() {
+            Some(_) => Ok(self.process.send_signal(signal)?),
+            // the pid is the one seen from the zygote, which may be in another pid namespace
+            None => self.signal_from_zygote(signal, 0),
+        }
+    }
+
+    /// Ask the zygote to send `signal` to the command, or to its process group `pgid`.
+    fn signal_from_zygote(&self, signal: i32, pgid: libc::pid_t) -> Result<(), Error> {
+        // one request at a time, so that the replies don't get mixed up
+        let _lock = self
+            .signals_lock
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        Ok(request_signal(&self.signals, signal, pgid)?)
+    }
+
+    /// Send `signal` to the process group of the command, e.g., `SIGSTOP`
+    /// or `SIGCONT` to stop or continue a whole pipeline.
+    ///
+    /// This fails if the command was not put in a process group of its own,
+    /// or in one it joined, see [`Command::process_group()`] and [`Command::setsid()`].
+    /// It also fails with `ESRCH` once the zygote reaped the command, even if
+    /// other processes remain in the group.
+    pub fn signal_group(&self, signal: i32) -> Result<(), Error> {
+        let Some(pgid) = self.pgid else {
+            let msg = "the command is in the process group of the zygote";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg).into());
+        };
+        if self.exit_status.is_some() {
+            return Err(io::Error::from_raw_os_error(libc::ESRCH).into());
+        }
+        if let Some(pidfd) = self.process.pidfd() {
+            let res = unsafe {
+                let flags = PIDFD_SIGNAL_PROCESS_GROUP;
+                libc::syscall(
+                    libc::SYS_pidfd_send_signal,
+                    pidfd.as_raw_fd(),
+                    signal,
+                    0,
+                    flags,
+                )
+            };
+            if res == 0 {
+                return Ok(());
+            }
+        }
+        // the pgid is the one seen from the zygote, if the kernel can't find the group for us
+        self.signal_from_zygote(signal, pgid)
+    }
+
+    /// Kill the command with `SIGKILL`.
+    pub fn kill(&self) -> Result<(), Error> {
+        self.send_signal(SIGKILL)
+    }
+
+    /// Change the size of the pseudo-terminal of the command to `rows` by `cols`
+    /// characters. The command receives a `SIGWINCH` signal.
+    pub fn resize_pty(&self, rows: u16, cols: u16) -> Result<(), Error> {
+        let pty = self
+            .pty
+            .as_ref()
+            .ok_or(io::Error::from(io::ErrorKind::NotFound))?;
+        Ok(set_window_size(pty.as_raw_fd(), rows, cols)?)
+    }
+
+    /// Wait for the command to exit, and get its exit status.
+    pub fn wait(&mut self) -> Result<ExitStatus, Error> {
+        if let Some(status) = self.exit_status {
+            return Ok(status);
+        }
+        let mut status = [0u8; 4];
+        self.status
+            .read_exact(&mut status)
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::UnexpectedEof => io::Error::new(
+                    err.kind(),
+                    "the zygote exited before reporting the exit status",
+                ),
+                _ => err,
+            })?;
+        let status = ExitStatus::from_raw(i32::from_ne_bytes(status));
+        self.exit_status = Some(status);
+        Ok(status)
+    }
+
+    /// Get the exit status of the command if it has already exited,
+    /// without blocking.
+    pub fn try_wait(&mut self) -> Result<Option<ExitStatus>, Error> {
+        if self.exit_status.is_none() {
+            let mut fds = [PollFd::new(self.status.as_fd(), PollFlags::POLLIN)];
+            match poll(&mut fds, PollTimeout::ZERO) {
+                Ok(0) | Err(Errno::EINTR) => return Ok(None),
+                Ok(_) => {}
+                Err(err) => return Err(io::Error::from(err).into()),
+            }
+        }
+        self.wait().map(Some)
+    }
+}
+
+/// Start `command`, this runs in the zygote.
+fn spawn(command: Command) -> Result<Child, WireError> {
+    // the ends of the streams the command uses, and the ones we return
+    let mut fds = vec![];
+    let mut ends: [Option<WireFd<File>>; 3] = Default::default();
+    let pty = match command.pty {
+        Some((rows, cols)) => Some(open_pty(rows, cols)?),
+        None => None,
+    };
+    for (target, stdio) in command.stdio.iter().enumerate() {
+        let fd = match (stdio, &pty) {
+            (Stdio::Inherit, Some((_, tty))) => Arc::clone(tty),
+            (Stdio::Inherit, None) => continue,
+            (Stdio::Null, _) => {
+                let null = File::options().read(true).write(true).open("/dev/null");
+                let null = null.map_err(|err| WireError::from(err).context("open /dev/null"))?;
+                Arc::new(OwnedFd::from(null))
+            }
+            (Stdio::Piped, _) => {
+                let (reader, writer) = pipe()?;
+                let (theirs, ours) = match target {
+                    0 => (reader, writer),
+                    _ => (writer, reader),
+                };
+                ends[target] = Some(WireFd::new(File::from(ours)));
+                Arc::new(theirs)
+            }
+            (Stdio::Fd(fd), _) => Arc::clone(fd),
+        };
+        fds.push((target as RawFd, fd));
+    }
+    let mapped = command
+        .fds
+        .iter()
+        .map(|(target, fd)| (*target, Arc::clone(fd)));
+    fds.extend(mapped);
+
+    let tty = pty.as_ref().map(|(_, tty)| tty.as_raw_fd());
+    let exec = Exec::new(&command, &fds, tty)?;
+    let (reader, writer) = pipe()?;
+    let process = exec.spawn()?;
+    let pid = process.pid()?;
+    let pgid = match (
+        command.session || command.pty.is_some(),
+        command.process_group,
+    ) {
+        (true, _) | (_, Some(0)) => Some(pid),
+        (false, pgid) => pgid,
+    };
+    let (signals, ours) = socketpair(
+        AddressFamily::Unix,
+        SockType::SeqPacket,
+        None,
+        SockFlag::SOCK_CLOEXEC,
+    )?;
+    crate::add_child(process.try_clone()?, Some(File::from(writer)), Some(ours));
+    let [stdin, stdout, stderr] = ends;
+    let pty = pty.map(|(pty, _)| WireFd::new(pty));
+    Ok(Child {
+        stdin,
+        stdout,
+        stderr,
+        pty,
+        process,
+        pid: pid as u32,
+        pgid,
+        status: WireFd::new(File::from(reader)),
+        signals: WireFd::new(signals),
+        signals_lock: Mutex::new(()),
+        exit_status: None,
+    })
+}
+
+/// Ask the zygote to send `signal` to a command through its `signals` socket,
+/// or to the process group `pgid`, if not 0.
+fn request_signal(signals: &OwnedFd, signal: i32, pgid: libc::pid_t) -> io::Result<()> {
+    // the zygote closes its end once it reaps the command
+    let gone = || io::Error::from_raw_os_error(libc::ESRCH);
+    let socket = signals.as_raw_fd();
+    let request = [signal.to_ne_bytes(), pgid.to_ne_bytes()].concat();
+    match send(socket, &request, MsgFlags::MSG_NOSIGNAL) {
+        Ok(_) => {}
+        Err(Errno::EPIPE | Errno::ECONNRESET) => return Err(gone()),
+        Err(err) => return Err(err.into()),
+    }
+    let mut errno = [0u8; size_of::<i32>()];
+    match recv(socket, &mut errno, MsgFlags::empty()) {
+        Ok(len) if len == errno.len() => match i32::from_ne_bytes(errno) {
+            0 => Ok(()),
+            errno => Err(io::Error::from_raw_os_error(errno)),
+        },
+        Ok(_) | Err(Errno::ECONNRESET) => Err(gone()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Serve a request to signal `child`, or a process group, queued on `signals`,
+/// if any, sending back the errno. This runs in the zygote, before it reaps
+/// the child, so that its pid can't be reused in the meantime.
+/// Returns `false` once the [`Child`] is gone.
+pub(crate) fn serve_signal(child: &Process, signals: &OwnedFd) -> bool {
+    let socket = signals.as_raw_fd();
+    let mut request = [0u8; 2 * size_of::<i32>()];
+    let res = match recv(socket, &mut request, MsgFlags::MSG_DONTWAIT) {
+        Err(Errno::EAGAIN | Errno::EINTR) => return true,
+        Ok(0) | Err(_) => return false,
+        Ok(len) if len == request.len() => {
+            let (signal, pgid) = request.split_at(size_of::<i32>());
+            let signal = i32::from_ne_bytes(signal.try_into().unwrap());
+            match libc::pid_t::from_ne_bytes(pgid.try_into().unwrap()) {
+                0 => child.send_signal(signal),
+                pgid => match unsafe { libc::killpg(pgid, signal) } {
+                    -1 => Err(io::Error::last_os_error()),
+                    _ => Ok(()),
+                },
+            }
+        }
+        Ok(_) => Err(io::Error::from_raw_os_error(libc::EINVAL)),
+    };
+    let errno = res.map_or_else(|err| err.raw_os_error().unwrap_or(libc::EIO), |_| 0);
+    let flags = MsgFlags::MSG_DONTWAIT | MsgFlags::MSG_NOSIGNAL;
+    send(socket, &errno.to_ne_bytes(), flags).is_ok()
+}
+
+/// Everything the command needs before calling `execve`, prepared in advance,
+/// as it can't allocate while it shares the memory of the zygote.
+struct Exec {
+    program: CString,
+    argv: Vec<*const libc::c_char>,
+    envp: Vec<*const libc::c_char>,
+    current_dir: Option<CString>,
+    /// The `(fd, target)` pairs to set up, and room to move the fds
+    /// out of the way of the targets.
+    fds: Vec<(RawFd, RawFd)>,
+    moved: Vec<RawFd>,
+    /// The fds to keep open, sorted, i.e., the standard streams and the targets.
+    keep: Vec<RawFd>,
+    /// Any fd above the targets.
+    min_fd: RawFd,
+    /// The highest fd to close if `close_range` is not available.
+    max_fd: RawFd,
+    /// The terminal to make the controlling terminal, if any.
+    tty: Option<RawFd>,
+    session: bool,
+    process_group: Option<libc::pid_t>,
+    foreground: bool,
+    parent: libc::pid_t,
+    parent_death_signal: i32,
+    /// The write end of the pipe to report errors through.
+    error: RawFd,
+    /// The strings `argv` and `envp` point to.
+    _strings: Vec<CString>,
+}
+
+/// Where the command failed, reported along with the errno.
+#[derive(Clone, Copy)]
+#[repr(i32)]
+enum Stage {
+    Fds = 1,
+    Chdir,
+    Exec,
+    Tty,
+    Session,
+}
+
+const STACK_SIZE: usize = 64 << 10;
+
+const PIDFD_SIGNAL_PROCESS_GROUP: libc::c_uint = 1 << 2;
+
+impl Exec {
+    fn new(
+        command: &Command,
+        fds: &[(RawFd, Arc<OwnedFd>)],
+        tty: Option<RawFd>,
+    ) -> Result<Exec, WireError> {
+        let mut env: Vec<(OsString, OsString)> = match command.env_clear {
+            true => vec![],
+            false => std::env::vars_os().collect(),
+        };
+        for (key, value) in &command.env {
+            env.retain(|(k, _)| k != key);
+            if let Some(value) = value {
+                env.push((key.clone(), value.clone()));
+            }
+        }
+        let path = env
+            .iter()
+            .find(|(k, _)| k == "PATH")
+            .map(|(_, v)| v.clone());
+        let program = cstring(resolve(&command.program, path).into_vec())?;
+
+        let mut strings = vec![];
+        for arg in std::iter::once(&command.program).chain(&command.args) {
+            strings.push(cstring(arg.as_bytes().to_vec())?);
+        }
+        let argc = strings.len();
+        for (key, value) in env {
+            let mut var = key.into_vec();
+            var.push(b'=');
+            var.extend(value.into_vec());
+            strings.push(cstring(var)?);
+        }
+        let ptrs = |strings: &[CString]| {
+            let ptrs = strings.iter().map(|s| s.as_ptr());
+            ptrs.chain([std::ptr::null()]).collect()
+        };
+        let argv = ptrs(&strings[..argc]);
+        let envp = ptrs(&strings[argc..]);
+
+        let current_dir = match &command.current_dir {
+            Some(dir) => Some(cstring(dir.as_os_str().as_bytes().to_vec())?),
+            None => None,
+        };
+
+        let fds: Vec<_> = fds
+            .iter()
+            .map(|(target, fd)| (fd.as_raw_fd(), *target))
+            .collect();
+        let mut keep: Vec<_> = [0, 1, 2]
+            .into_iter()
+            .chain(fds.iter().map(|fd| fd.1))
+            .collect();
+        keep.sort();
+        keep.dedup();
+        let min_fd = keep.last().unwrap() + 1;
+        let max_fd =
+            match nix::sys::resource::getrlimit(nix::sys::resource::Resource::RLIMIT_NOFILE) {
+                Ok((soft, _)) => soft.min(RawFd::MAX as u64) as RawFd,
+                Err(_) => 1024,
+            };
+
+        Ok(Exec {
+            program,
+            argv,
+            envp,
+            current_dir,
+            moved: vec![-1; fds.len()],
+            fds,
+            keep,
+            min_fd,
+            max_fd,
+            tty,
+            session: command.session || tty.is_some(),
+            // a session leader already leads its own group
+            process_group: command
+                .process_group
+                .filter(|_| !(command.session || tty.is_some())),
+            foreground: command.foreground,
+            parent: unsafe { libc::getpid() },
+            parent_death_signal: command.parent_death_signal.unwrap_or(0),
+            error: -1,
+            _strings: strings,
+        })
+    }
+
+    /// Start the command, and wait for it to call `execve`.
+    fn spawn(mut self) -> Result<Process, WireError> {
+        let (reader, writer) = pipe()?;
+        self.error = writer.as_raw_fd();
+
+        // the command needs its own stack, as it shares our memory
+        let mut stack = vec![0u8; STACK_SIZE];
+        let stack_top = unsafe { stack.as_mut_ptr().add(stack.len()) };
+        let stack_top = stack_top.wrapping_sub(stack_top as usize % 16);
+
+        // block every signal, so that no handler runs in the command
+        // before it resets them to their default
+        let mut mask = unsafe { std::mem::zeroed::<libc::sigset_t>() };
+        let mut old_mask = unsafe { std::mem::zeroed::<libc::sigset_t>() };
+        unsafe { libc::sigfillset(&mut mask) };
+        unsafe { libc::pthread_sigmask(libc::SIG_SETMASK, &mask, &mut old_mask) };
+        // kernels before 5.2 ignore CLONE_PIDFD, and leave the pidfd untouched
+        let mut pidfd: RawFd = -1;
+        let pid = unsafe {
+            libc::clone(
+                exec_child,
+                stack_top as *mut libc::c_void,
+                CLONE_VM | CLONE_VFORK | CLONE_PIDFD | SIGCHLD,
+                std::ptr::from_mut(&mut self) as *mut libc::c_void,
+                &mut pidfd,
+            )
+        };
+        let err = io::Error::last_os_error();
+        unsafe { libc::pthread_sigmask(libc::SIG_SETMASK, &old_mask, std::ptr::null_mut()) };
+        drop(writer);
+        if pid == -1 {
+            return Err(WireError::from(err).context("spawn the command"));
+        }
+        let process = Process::from_raw(pidfd, pid);
+
+        // the pipe is closed on a successful execve, otherwise we get the error
+        let mut error = [0u8; 8];
+        match File::from(reader).read_exact(&mut error) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(process),
+            Err(err) => return Err(err.into()),
+        }
+        let _ = process.wait();
+        let stage = i32::from_ne_bytes(error[..4].try_into().unwrap());
+        let errno = i32::from_ne_bytes(error[4..].try_into().unwrap());
+        let what = match stage {
+            s if s == Stage::Fds as i32 => "set up the file descriptors".into(),
+            s if s == Stage::Chdir as i32 => format!("change directory to {:?}", self.current_dir),
+            s if s == Stage::Tty as i32 => "set up the controlling terminal".into(),
+            s if s == Stage::Session as i32 => "set up the process group".into(),
+            _ => format!("execute {:?}", self.program),
+        };
+        Err(WireError::from(io::Error::from_raw_os_error(errno)).context(what))
+    }
+}
+
+/// The entry point of the command. It runs on its own stack, but shares the
+/// memory of the zygote until it calls `execve`, so it must not allocate
+/// or otherwise touch any state of the zygote but `exec`.
+extern "C" fn exec_child(exec: *mut libc::c_void) -> libc::c_int {
+    let exec = unsafe { &mut *(exec as *mut Exec) };
+    unsafe {
+        // reset the signals the zygote handles, and SIGPIPE, which the rust
+        // runtime ignores, before unblocking them
+        let mut action = std::mem::zeroed::<libc::sigaction>();
+        for signal in 1..=libc::SIGRTMAX() {
+            let res = libc::sigaction(signal, std::ptr::null(), &mut action);
+            let handled =
+                action.sa_sigaction != libc::SIG_DFL && action.sa_sigaction != libc::SIG_IGN;
+            if res == 0 && (handled || signal == libc::SIGPIPE) {
+                libc::signal(signal, libc::SIG_DFL);
+            }
+        }
+
+        if exec.parent_death_signal != 0 {
+            libc::prctl(libc::PR_SET_PDEATHSIG, exec.parent_death_signal);
+            // the zygote is stopped until we exec, it can only have been killed
+            if libc::getppid() != exec.parent {
+                libc::_exit(127);
+            }
+        }
+
+        if exec.session && libc::setsid() == -1 {
+            fail(exec.error, Stage::Session);
+        }
+        if let Some(tty) = exec.tty {
+            if libc::ioctl(tty, libc::TIOCSCTTY, 0) == -1 {
+                fail(exec.error, Stage::Tty);
+            }
+        }
+        if let Some(pgid) = exec.process_group {
+            if libc::setpgid(0, pgid) == -1 {
+                fail(exec.error, Stage::Session);
+            }
+        }
+        if exec.foreground {
+            // SIGTTOU is still blocked, so we can do this from the background
+            let tty = match exec.tty {
+                Some(tty) => tty,
+                None => libc::open(c"/dev/tty".as_ptr(), libc::O_RDWR | libc::O_CLOEXEC),
+            };
+            if tty == -1 || libc::tcsetpgrp(tty, libc::getpgrp()) == -1 {
+                fail(exec.error, Stage::Tty);
+            }
+        }
+
+        // move the error pipe and the fds out of the way of the targets
+        let error = libc::fcntl(exec.error, libc::F_DUPFD_CLOEXEC, exec.min_fd);
+        if error == -1 {
+            fail(exec.error, Stage::Fds);
+        }
+        for ((fd, _), moved) in exec.fds.iter().zip(exec.moved.iter_mut()) {
+            *moved = libc::fcntl(*fd, libc::F_DUPFD_CLOEXEC, exec.min_fd);
+            if *moved == -1 {
+                fail(error, Stage::Fds);
+            }
+        }
+        for ((_, target), moved) in exec.fds.iter().zip(exec.moved.iter()) {
+            // dup2 clears the close-on-exec flag of the target
+            if libc::dup2(*moved, *target) == -1 {
+                fail(error, Stage::Fds);
+            }
+        }
+
+        // close everything else but the error pipe, which is close-on-exec
+        let mut from = 0;
+        for to in exec.keep.iter().copied().chain([RawFd::MAX]) {
+            if (from..to).contains(&error) {
+                close_range(from, error - 1, exec.max_fd);
+                close_range(error + 1, to - 1, exec.max_fd);
+            } else {
+                close_range(from, to - 1, exec.max_fd);
+            }
+            from = to.saturating_add(1);
+        }
+
+        if let Some(dir) = &exec.current_dir {
+            if libc::chdir(dir.as_ptr()) == -1 {
+                fail(error, Stage::Chdir);
+            }
+        }
+
+        let mut mask = std::mem::zeroed::<libc::sigset_t>();
+        libc::sigemptyset(&mut mask);
+        libc::pthread_sigmask(libc::SIG_SETMASK, &mask, std::ptr::null_mut());
+
+        libc::execve(
+            exec.program.as_ptr(),
+            exec.argv.as_ptr(),
+            exec.envp.as_ptr(),
+        );
+        fail(error, Stage::Exec);
+    }
+}
+
+/// Close the fds from `first` to `last`, falling back to closing them
+/// one by one up to `max_fd` on kernels before 5.9.
+pub(crate) unsafe fn close_range(first: RawFd, last: RawFd, max_fd: RawFd) {
+    if first > last {
+        return;
+    }
+    let res = libc::syscall(libc::SYS_close_range, first as u32, last as u32, 0);
+    if res == -1 {
+        for fd in first..=last.min(max_fd) {
+            libc::close(fd);
+        }
+    }
+}
+
+/// Report the current errno through the `error` pipe, and exit.
+fn fail(error: RawFd, stage: Stage) -> ! {
+    let errno = Errno::last_raw();
+    let mut buf = [0u8; 8];
+    buf[..4].copy_from_slice(&(stage as i32).to_ne_bytes());
+    buf[4..].copy_from_slice(&errno.to_ne_bytes());
+    unsafe {
+        libc::write(error, buf.as_ptr() as *const libc::c_void, buf.len());
+        libc::_exit(127);
+    }
+}
+
+/// Open a new pseudo-terminal of `rows` by `cols` characters,
+/// returning its controlling end and the terminal itself.
+fn open_pty(rows: u16, cols: u16) -> Result<(File, Arc<OwnedFd>), WireError> {
+    let fail = |err| WireError::from(err).context("open a pseudo-terminal");
+    let flags = libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC;
+    let pty = match unsafe { libc::posix_openpt(flags) } {
+        -1 => return Err(fail(io::Error::last_os_error())),
+        fd => unsafe { File::from_raw_fd(fd) },
+    };
+    if unsafe { libc::grantpt(pty.as_raw_fd()) } == -1
+        || unsafe { libc::unlockpt(pty.as_raw_fd()) } == -1
+    {
+        return Err(fail(io::Error::last_os_error()));
+    }
+    let mut name = [0 as libc::c_char; 64];
+    let res = unsafe { libc::ptsname_r(pty.as_raw_fd(), name.as_mut_ptr(), name.len()) };
+    if res != 0 {
+        return Err(fail(io::Error::from_raw_os_error(res)));
+    }
+    let tty = match unsafe { libc::open(name.as_ptr(), flags) } {
+        -1 => return Err(fail(io::Error::last_os_error())),
+        fd => unsafe { OwnedFd::from_raw_fd(fd) },
+    };
+    set_window_size(pty.as_raw_fd(), rows, cols).map_err(fail)?;
+    Ok((pty, Arc::new(tty)))
+}
+
+fn set_window_size(pty: RawFd, rows: u16, cols: u16) -> io::Result<()> {
+    let size = libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    match unsafe { libc::ioctl(pty, libc::TIOCSWINSZ, &size) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Find `program` in `path`, like `execvp` would.
+/// Returns `program` as is if it's not found, for `execve` to fail.
+fn resolve(program: &OsStr, path: Option<OsString>) -> OsString {
+    if program.as_bytes().contains(&b'/') {
+        return program.to_owned();
+    }
+    let path = path.unwrap_or_else(|| "/usr/local/bin:/usr/bin:/bin".into());
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(program))
+        .find(|candidate| is_executable(candidate))
+        .map_or_else(|| program.to_owned(), PathBuf::into_os_string)
+}
+
+fn is_executable(path: &Path) -> bool {
+    let Ok(cpath) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    path.is_file() && unsafe { libc::access(cpath.as_ptr(), libc::X_OK) } == 0
+}
+
+fn cstring(bytes: Vec<u8>) -> Result<CString, WireError> {
+    CString::new(bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err).into())
+}
+
+fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [-1; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::os::fd::AsFd as _;
+    use std::os::unix::process::{CommandExt as _, ExitStatusExt as _};
+    use std::time::{Duration, Instant};
+
+    use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+    use nix::sys::socket::{socketpair, AddressFamily, SockFlag, SockType};
+
+    use super::{request_signal, serve_signal};
+    use crate::process::Process;
+
+    #[test]
+    fn signal_pid_only() {
+        let pid = std::process::Command::new("sleep")
+            .arg("1000")
+            .process_group(0)
+            .spawn()
+            .unwrap()
+            .id();
+        let process = Process::Pid(pid as libc::pid_t);
+        let (theirs, ours) = socketpair(
+            AddressFamily::Unix,
+            SockType::SeqPacket,
+            None,
+            SockFlag::SOCK_CLOEXEC,
+        )
+        .unwrap();
+
+        let request = |theirs, signal, pgid| {
+            let requests = std::thread::spawn(move || {
+                let res = request_signal(&theirs, signal, pgid);
+                (res.map_err(|err| err.raw_os_error()), theirs)
+            });
+            let mut fds = [PollFd::new(ours.as_fd(), PollFlags::POLLIN)];
+            poll(&mut fds, PollTimeout::NONE).unwrap();
+            assert!(serve_signal(&process, &ours));
+            requests.join().unwrap()
+        };
+        let (res, theirs) = request(theirs, libc::SIGSTOP, pid as _);
+        assert_eq!(res, Ok(()));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !fs::read_to_string(format!("/proc/{pid}/stat"))
+            .unwrap()
+            .contains(") T ")
+        {
+            assert!(Instant::now() < deadline, "the group didn't stop");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let (res, theirs) = request(theirs, libc::SIGKILL, 0);
+        assert_eq!(res, Ok(()));
+        let status = process.wait().unwrap().unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGKILL));
+
+        // once the command is reaped, the zygote drops its end
+        drop(ours);
+        let res = request_signal(&theirs, libc::SIGKILL, 0);
+        assert_eq!(res.unwrap_err().raw_os_error(), Some(libc::ESRCH));
+    }
+}
@@ -1,14 +1,18 @@
+use std::any::TypeId;
 use std::backtrace::{Backtrace, BacktraceStatus};
 use std::error::Error as StdError;
 use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use std::io;
 use std::mem::transmute;
 use std::ops::{Deref, DerefMut};
 use std::panic::PanicHookInfo;
 
+use nix::errno::Errno;
 use serde::{Deserialize, Serialize};
 
 /// Error type used by [`Zygote::try_run()`](crate::Zygote::try_run) when running a task.
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
     /// Error during an IO operation
     #[error("io error: {0}")]
@@ -25,6 +29,21 @@ pub enum Error {
     /// Error originating in the zygote process, including task panics.
     #[error("wire error: {0}")]
     Wire(#[from] WireError),
+
+    /// The zygote handle was sent to another process
+    #[error("the zygote handle was moved to another process")]
+    Moved,
+
+    /// The zygote process died while running a task, e.g., because it
+    /// exceeded one of its resource limits (see [`ZygoteBuilder::rlimit()`](crate::ZygoteBuilder::rlimit)).
+    #[error("the zygote process died ({0})")]
+    Died(std::process::ExitStatus),
+
+    /// The zygote process made a system call forbidden by its seccomp policy
+    /// (see [`ZygoteBuilder::seccomp()`](crate::ZygoteBuilder::seccomp)), and it was terminated.
+    #[cfg(feature = "seccomp")]
+    #[error("the zygote process made a forbidden system call: {0}")]
+    ForbiddenSyscall(String),
 }
 
 /// A serializable error type.
@@ -70,6 +89,7 @@ struct WireErrorInner {
     pub(crate) description: String,
     pub(crate) source: Option<Box<WireErrorInner>>,
     pub(crate) backtrace: Option<String>,
+    pub(crate) errno: Option<i32>,
 }
 
 impl WireErrorInner {
@@ -97,6 +117,27 @@ impl WireError {
     pub fn backtrace(&self) -> Option<&str> {
         self.0.backtrace.as_deref()
     }
+
+    /// Returns the OS error code of this error or of its first source
+    /// that has one, e.g., if it was converted from a [`std::io::Error`].
+    ///
+    /// ```rust
+    /// # use zygote::{WireError, Zygote};
+    /// let res = Zygote::global().run(|_| -> Result<Vec<u8>, WireError> {
+    ///     Ok(std::fs::read("/does/not/exist")?)
+    /// }, ());
+    /// assert_eq!(res.unwrap_err().raw_os_error(), Some(libc::ENOENT));
+    /// ```
+    pub fn raw_os_error(&self) -> Option<i32> {
+        let mut err = Some(self);
+        while let Some(e) = err {
+            if e.0.errno.is_some() {
+                return e.0.errno;
+            }
+            err = e.source();
+        }
+        None
+    }
 }
 
 impl WireError {
@@ -105,15 +146,55 @@ impl WireError {
             description: err.as_ref().to_owned(),
             source: None,
             backtrace: None,
+            errno: None,
         }
         .into_wire_error()
     }
 
-    pub(crate) fn from_err(err: &(impl StdError + ?Sized)) -> Self {
+    /// Prefix the description of this error with `what` failed,
+    /// keeping its OS error code.
+    pub(crate) fn context(self, what: impl Display) -> Self {
+        WireErrorInner {
+            description: format!("failed to {what}: {self}"),
+            source: None,
+            backtrace: None,
+            errno: self.raw_os_error(),
+        }
+        .into_wire_error()
+    }
+
+    /// Set the lower-level source of this error, unless it already has one.
+    pub(crate) fn with_source(mut self, source: WireError) -> Self {
+        self.0.source.get_or_insert(Box::new(source.0));
+        self
+    }
+
+    pub(crate) fn from_err<E: StdError + ?Sized>(err: &E) -> Self {
+        // `E` might not be 'static, and can't be downcast
+        let errno = if typeid::of::<E>() == TypeId::of::<io::Error>() {
+            unsafe { &*(err as *const E).cast::<io::Error>() }.raw_os_error()
+        } else if typeid::of::<E>() == TypeId::of::<Errno>() {
+            Some(unsafe { *(err as *const E).cast::<Errno>() } as i32)
+        } else {
+            None
+        };
+        Self::with_errno(err, errno)
+    }
+
+    fn from_source(err: &(dyn StdError + 'static)) -> Self {
+        let errno = match err.downcast_ref::<io::Error>() {
+            Some(err) => err.raw_os_error(),
+            None => err.downcast_ref::<Errno>().map(|e| *e as i32),
+        };
+        Self::with_errno(err, errno)
+    }
+
+    fn with_errno(err: &(impl StdError + ?Sized), errno: Option<i32>) -> Self {
         WireErrorInner {
             description: err.to_string(),
-            source: err.source().map(|src| Box::new(Self::from_err(src).0)),
+            source: err.source().map(|src| Box::new(Self::from_source(src).0)),
             backtrace: None,
+            errno,
         }
         .into_wire_error()
     }
@@ -124,6 +205,7 @@ impl WireError {
             source: None,
             backtrace: (backtrace.status() == BacktraceStatus::Captured)
                 .then_some(backtrace.to_string()),
+            errno: None,
         }
         .into_wire_error()
     }
@@ -0,0 +1,116 @@
+use std::path::PathBuf;
+
+use ::landlock::{
+    path_beneath_rules, Access, AccessFs, CompatLevel, Compatible, Ruleset, RulesetAttr,
+    RulesetCreatedAttr, ABI,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::WireError;
+
+/// A Landlock policy restricting the filesystem access of a zygote.
+///
+/// Once the policy is installed, the zygote can only access the files
+/// beneath the allowed paths, any other access is denied. Denied accesses
+/// fail with `EACCES`, which tasks can report through a [`WireError`]
+/// (see [`WireError::raw_os_error()`]).
+/// Paths that don't exist when the policy is installed are ignored.
+///
+/// By default, the policy is applied on a best-effort basis: the zygote is
+/// created unrestricted on kernels without Landlock support, see
+/// [`LandlockPolicy::required()`].
+///
+/// ```rust
+/// # use zygote::{LandlockPolicy, WireError, Zygote};
+/// let dir = std::env::temp_dir().join(format!("zygote-doctest-landlock-{}", std::process::id()));
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let zygote = Zygote::builder()
+///     .landlock(LandlockPolicy::new().read_write(&dir))
+///     .build()
+///     .unwrap();
+///
+/// let res = zygote.run(|dir: std::path::PathBuf| -> Result<(), WireError> {
+///     Ok(std::fs::write(dir.join("file.txt"), "hello")?)
+/// }, &dir);
+/// assert!(res.is_ok());
+///
+/// let res = zygote.run(|_| -> Result<Vec<u8>, WireError> {
+///     Ok(std::fs::read("/etc/hostname")?)
+/// }, ());
+/// # if zygote::LandlockPolicy::is_supported() {
+/// assert_eq!(res.unwrap_err().raw_os_error(), Some(libc::EACCES));
+/// # }
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct LandlockPolicy {
+    read_only: Vec<PathBuf>,
+    read_write: Vec<PathBuf>,
+    required: bool,
+}
+
+impl LandlockPolicy {
+    /// Create a policy that denies access to every path.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow reading, listing and executing files beneath `path`.
+    pub fn read_only(mut self, path: impl Into<PathBuf>) -> Self {
+        self.read_only.push(path.into());
+        self
+    }
+
+    /// Allow reading and modifying files beneath `path`.
+    pub fn read_write(mut self, path: impl Into<PathBuf>) -> Self {
+        self.read_write.push(path.into());
+        self
+    }
+
+    /// Fail to create the zygote if the kernel doesn't support Landlock,
+    /// instead of leaving the zygote unrestricted. Defaults to `false`.
+    pub fn required(mut self, required: bool) -> Self {
+        self.required = required;
+        self
+    }
+
+    /// Check if the running kernel supports Landlock.
+    pub fn is_supported() -> bool {
+        let version = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<libc::c_void>(),
+                0,
+                1, // LANDLOCK_CREATE_RULESET_VERSION
+            )
+        };
+        version > 0
+    }
+
+    /// Install the policy in the calling thread.
+    pub(crate) fn install(&self) -> Result<(), WireError> {
+        let level = match self.required {
+            true => CompatLevel::HardRequirement,
+            false => CompatLevel::BestEffort,
+        };
+        // require the restrictions of the first ABI version, and
+        // use any later one the kernel supports
+        let abi = ABI::V6;
+        Ruleset::default()
+            .set_compatibility(level)
+            .handle_access(AccessFs::from_all(ABI::V1))?
+            .set_compatibility(CompatLevel::BestEffort)
+            .handle_access(AccessFs::from_all(abi))?
+            .create()?
+            .add_rules(path_beneath_rules(
+                &self.read_only,
+                AccessFs::from_read(abi),
+            ))?
+            .add_rules(path_beneath_rules(
+                &self.read_write,
+                AccessFs::from_all(abi),
+            ))?
+            .restrict_self()?;
+        Ok(())
+    }
+}
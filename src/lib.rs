@@ -13,28 +13,90 @@
 //!     assert_ne!(pid, std::process::id());
 //! }
 //! ```
+//!
+//! # Logging
+//! With the `log` and `tracing` features, the records a task emits are
+//! forwarded to the process that sent it, and emitted again there, with the
+//! pid of the zygote and the location of the task attached. See
+//! `LogForwarder` if the application installs its logger before creating
+//! zygotes.
+//!
+//! Forwarded `tracing` events are emitted within the current span of the
+//! caller, and within a `zygote` span for each span they were emitted in
+//! by the task, carrying its name and fields. The task itself doesn't see
+//! the spans of the caller, e.g., through `tracing::Span::current()`.
+
+// some doctests predate the never type fallback changes of edition 2024
+#![doc(test(attr(allow(dependency_on_unit_never_type_fallback))))]
 
 use std::backtrace::Backtrace;
-use std::cell::Cell;
-use std::io;
-use std::io::ErrorKind::{BrokenPipe, UnexpectedEof};
+use std::cell::{Cell, RefCell};
+use std::fs::File;
+use std::io::ErrorKind::{BrokenPipe, ConnectionReset, UnexpectedEof};
+use std::io::{self, Write as _};
 use std::mem::transmute;
-use std::os::fd::{AsFd as _, AsRawFd, FromRawFd, OwnedFd};
-use std::panic::{catch_unwind, set_hook, take_hook};
-use std::sync::{LazyLock, Mutex};
+use std::os::fd::{AsFd as _, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::os::unix::process::ExitStatusExt as _;
+use std::panic::{catch_unwind, set_hook, take_hook, Location, UnwindSafe};
+use std::process::ExitStatus;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::SeqCst;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
 
+pub use broker::{Broker, BrokerPolicy};
+pub use builder::{DropPolicy, Network, Resource, ZygoteBuilder};
+pub use caps::Capability;
+pub use capture::Output;
+pub use cgroup::Cgroup;
+use clone::clone3_or_clone;
+pub use command::{Child, Command, Stdio};
 pub use error::{Error, WireError};
 pub use fd::WireFd;
-use libc::{CLONE_PARENT, SIGCHLD, SIGKILL};
-use nix::sched::CloneFlags;
-use nix::sys::wait::{waitid, Id, WaitPidFlag};
-use pipe::Pipe;
-use serde::{Deserialize, Serialize};
+#[cfg(feature = "landlock")]
+pub use landlock::LandlockPolicy;
+use libc::{CLONE_NEWPID, CLONE_PARENT, PR_SET_CHILD_SUBREAPER, SIGCHLD, SIGKILL};
+#[cfg(feature = "log")]
+pub use logging::LogForwarder;
+pub use mounts::Mounts;
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+#[cfg(feature = "metrics")]
+pub use observer::MetricsObserver;
+pub use observer::{set_observer, Observer, TaskMetrics};
+use pipe::{Pipe, Traffic};
+use process::Process;
+#[cfg(feature = "seccomp")]
+pub use seccomp::SeccompPolicy;
+use serde::ser::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+pub use server::{Address, Allowlist};
+pub use stats::{Stats, ZygoteStats};
 use wire::{AsWire, Wire};
 
+mod broker;
+mod builder;
+mod capture;
+mod cgroup;
+mod clone;
+mod command;
 mod error;
 mod fd;
+#[cfg(feature = "landlock")]
+mod landlock;
+#[cfg(any(feature = "log", feature = "tracing"))]
+mod logging;
+mod mounts;
+mod namespace;
+mod observer;
 mod pipe;
+mod privileges;
+mod process;
+#[cfg(feature = "seccomp")]
+mod seccomp;
+mod server;
+mod stats;
 mod wire;
 
 /// Representation of a zygote process
@@ -43,14 +105,39 @@ mod wire;
 /// in it.
 ///
 /// Dropping this struct will result in the termination of the zygote
-/// process.
-#[repr(transparent)]
+/// process. This can be changed using [`ZygoteBuilder::drop_policy()`].
+///
+/// A zygote handle can be sent to and from other processes, e.g., as
+/// the argument or return value of a task, see [`Zygote::transfer()`].
+///
+/// ```rust
+/// # use zygote::{Transfer, Zygote};
+/// # fn getpid() -> libc::pid_t { unsafe { libc::getpid() } }
+/// let zygote1 = Zygote::new();
+/// let zygote2 = Zygote::new();
+/// let pid2 = zygote2.run(|_| getpid(), ());
+///
+/// // run a task in zygote2 from within zygote1, and get zygote2 back
+/// let (pid, zygote2) = zygote1.run(
+///     |z: Transfer| {
+///         let z = z.into_zygote();
+///         (z.run(|_| getpid(), ()), z.transfer())
+///     },
+///     zygote2.transfer(),
+/// );
+/// assert_eq!(pid, pid2);
+/// let zygote2 = zygote2.into_zygote();
+/// ```
 pub struct Zygote(ZygoteImpl);
 
-#[derive(Serialize, Deserialize)]
 struct ZygoteImpl {
-    pidfd: WireFd<OwnedFd>,
+    process: Process,
     pipe: Mutex<WireFd<Pipe>>,
+    moved: Arc<AtomicBool>,
+    drop_policy: DropPolicy,
+    kill_tree: bool,
+    /// The read end of the pipe seccomp violations are reported on.
+    violations: Option<WireFd<File>>,
 }
 
 impl Zygote {
@@ -89,6 +176,10 @@ impl Zygote {
 
     /// Obtain the global zygote process.
     /// This method initializes the global zygote if needed.
+    ///
+    /// Like any zygote, the global zygote only dies with its parent process
+    /// by default if it's initialized from the main thread,
+    /// see [`ZygoteBuilder::parent_death_signal()`].
     /// ```rust
     /// # use zygote::Zygote;
     /// Zygote::global().run(|_| std::process::id(), ());
@@ -141,27 +232,83 @@ impl Zygote {
     /// This method panics if any of the syscalls (creating a unix domain socket and
     /// cloning the process) fails.
     pub fn new() -> Zygote {
-        Self::new_impl(false)
+        ZygoteBuilder::new().build().unwrap()
+    }
+
+    /// Create a [`ZygoteBuilder`] to configure a new zygote process.
+    pub fn builder() -> ZygoteBuilder {
+        ZygoteBuilder::new()
     }
 
-    fn new_impl(sibling: bool) -> Zygote {
-        let (child_pipe, parent_pipe) = Pipe::pair().unwrap();
-        let pidfd = if sibling {
-            clone3_or_clone(CLONE_PARENT, 0).unwrap()
-        } else {
-            clone3_or_clone(0, SIGCHLD).unwrap()
+    fn new_impl(relation: Relation, builder: &ZygoteBuilder) -> Result<Zygote, Error> {
+        let (mut child_pipe, parent_pipe) = Pipe::pair()?;
+        // siblings are children of our own parent, that's the process to watch
+        let parent = match relation {
+            Relation::Child | Relation::Nested => unsafe { libc::getpid() },
+            Relation::Sibling => unsafe { libc::getppid() },
+        };
+        let signal = match (builder.parent_death_signal, relation) {
+            (Some(signal), _) => signal,
+            // the signal is sent when the creating thread exits, only the main
+            // thread is expected to live as long as its process
+            (None, Relation::Child | Relation::Nested) => {
+                let main_thread = unsafe { libc::gettid() == libc::getpid() };
+                main_thread.then_some(SIGKILL)
+            }
+            // siblings share the parent of the calling zygote, and its thread
+            (None, Relation::Sibling) => process::parent_death_signal(),
+        };
+        let parent = match signal {
+            Some(signal) => Some((Process::open(parent)?, signal)),
+            None => None,
         };
-        match pidfd {
+        let (flags, exit_signal) = match relation {
+            Relation::Child | Relation::Nested => (0, SIGCHLD),
+            Relation::Sibling => (CLONE_PARENT, 0),
+        };
+        let flags = flags | builder.namespaces();
+        let violations = violations_pipe(Some(builder))?;
+        let ids = namespace::Ids::current();
+        let cgroup = builder.cgroup.as_ref().map(|cgroup| cgroup.as_fd());
+        // the kernel kills the process tree along with the init of a pid namespace
+        let pid_namespace = flags & CLONE_NEWPID != 0;
+        let child = clone3_or_clone(flags, exit_signal, cgroup)?;
+        match child {
             None => {
                 drop(parent_pipe);
+                #[cfg(feature = "seccomp")]
+                if let Some((_, report)) = violations {
+                    seccomp::report_to(report);
+                }
+                if let Some((parent, signal)) = &parent {
+                    process::die_with_parent(parent, *signal);
+                }
+                let subreaper = builder.kill_tree && !pid_namespace;
+                if subreaper {
+                    unsafe { libc::prctl(PR_SET_CHILD_SUBREAPER, 1) };
+                }
+                KILL_TREE.set(subreaper);
+                // let our creator know whether we are ready to run tasks
+                let setup = builder.setup(ids, parent.as_ref());
+                let failed = setup.is_err();
+                if child_pipe.send(setup).is_err() || failed {
+                    std::process::exit(1);
+                }
+                if let Some(broker) = &builder.broker {
+                    broker::set_current(Some(broker.clone()));
+                }
                 zygote_start(child_pipe);
                 // unreachable
             }
-            Some(pidfd) => {
+            Some(child) => {
                 drop(child_pipe);
-                let pidfd = WireFd::new(pidfd);
-                let pipe = Mutex::new(WireFd::new(parent_pipe));
-                Zygote(ZygoteImpl { pidfd, pipe })
+                let mut zygote = Zygote::from_parts(child, parent_pipe);
+                zygote.0.drop_policy = builder.drop_policy;
+                zygote.0.kill_tree = builder.kill_tree && !pid_namespace;
+                zygote.0.violations = violations.map(|(pipe, _)| WireFd::new(pipe));
+                let pipe = zygote.0.pipe.get_mut().unwrap();
+                pipe.recv::<Result<(), WireError>>()??;
+                Ok(zygote)
             }
         }
     }
@@ -200,6 +347,7 @@ impl Zygote {
     /// This method panics if communication with the zygote fails or
     /// if the task itself panics.
     /// For a non panicking version of this method see [`Zygote::try_run()`].
+    #[track_caller]
     pub fn run<Args: Wire, Ret: Wire>(&self, f: fn(Args) -> Ret, args: impl AsWire<Args>) -> Ret {
         self.try_run(f, args).unwrap()
     }
@@ -216,17 +364,290 @@ impl Zygote {
     /// let res = zygote.try_run(|_| panic!("oops"), ()).unwrap_err();
     /// assert!(res.to_string().contains("oops"));
     /// ```
+    #[track_caller]
     pub fn try_run<Args: Wire, Ret: for<'b> Wire>(
         &self,
         f: fn(Args) -> Ret,
         args: impl AsWire<Args>,
     ) -> Result<Ret, Error> {
+        self.call(runner::<Args, Ret> as *const (), f as *const (), args)
+    }
+
+    /// Run a task in the zygote process, capturing what it writes to
+    /// its standard output and error.
+    ///
+    /// Without this, the output of a task goes wherever the output of the
+    /// zygote goes, usually interleaved with the output of the calling process.
+    /// ```rust
+    /// # use zygote::Zygote;
+    /// # let zygote = Zygote::new();
+    /// let (ret, output) = zygote.run_captured(|x: u32| {
+    ///     println!("doubling {x}");
+    ///     x * 2
+    /// }, 4);
+    /// assert_eq!(ret, 8);
+    /// assert_eq!(output.stdout, b"doubling 4\n");
+    /// ```
+    ///
+    /// Output written by other threads of the zygote while the task runs
+    /// is captured as well. If the task panics, what it wrote, including
+    /// the panic message, is the [source](WireError::source) of the error.
+    ///
+    /// # Panics
+    /// Same panic conditions as [`Zygote::run()`].
+    /// For a non panicking version of this method see [`Zygote::try_run_captured()`].
+    #[track_caller]
+    pub fn run_captured<Args: Wire, Ret: Wire>(
+        &self,
+        f: fn(Args) -> Ret,
+        args: impl AsWire<Args>,
+    ) -> (Ret, Output) {
+        self.try_run_captured(f, args).unwrap()
+    }
+
+    /// Run a task in the zygote process, capturing its output.
+    /// Like [`Zygote::run_captured()`], but the return value is a [`Result`] that will
+    /// error if the task panics or communication with the zygote fails.
+    #[track_caller]
+    pub fn try_run_captured<Args: Wire, Ret: for<'b> Wire>(
+        &self,
+        f: fn(Args) -> Ret,
+        args: impl AsWire<Args>,
+    ) -> Result<(Ret, Output), Error> {
+        self.call(
+            captured_runner::<Args, Ret> as *const (),
+            f as *const (),
+            args,
+        )
+    }
+
+    /// Run a task in the zygote process, measuring the resources it uses.
+    /// ```rust
+    /// # use zygote::Zygote;
+    /// # let zygote = Zygote::new();
+    /// let (ret, stats) = zygote.run_with_stats(|n: u64| (0..n).sum::<u64>(), 1000);
+    /// assert_eq!(ret, 499500);
+    /// assert!(stats.bytes_received > 0);
+    /// println!("took {:?} of CPU time", stats.user_time + stats.system_time);
+    /// ```
+    ///
+    /// # Panics
+    /// Same panic conditions as [`Zygote::run()`].
+    /// For a non panicking version of this method see [`Zygote::try_run_with_stats()`].
+    #[track_caller]
+    pub fn run_with_stats<Args: Wire, Ret: Wire>(
+        &self,
+        f: fn(Args) -> Ret,
+        args: impl AsWire<Args>,
+    ) -> (Ret, Stats) {
+        self.try_run_with_stats(f, args).unwrap()
+    }
+
+    /// Run a task in the zygote process, measuring the resources it uses.
+    /// Like [`Zygote::run_with_stats()`], but the return value is a [`Result`] that will
+    /// error if the task panics or communication with the zygote fails.
+    #[track_caller]
+    pub fn try_run_with_stats<Args: Wire, Ret: for<'b> Wire>(
+        &self,
+        f: fn(Args) -> Ret,
+        args: impl AsWire<Args>,
+    ) -> Result<(Ret, Stats), Error> {
+        let runner = stats_runner::<Args, Ret> as *const ();
+        let ((ret, mut stats), traffic) =
+            self.call_with_traffic::<_, (Ret, Stats)>(runner, f as *const (), args)?;
+        stats.bytes_sent = traffic.bytes_sent;
+        stats.bytes_received = traffic.bytes_received;
+        Ok((ret, stats))
+    }
+
+    /// Run a task in a new zygote spawned from this one, with its usage of
+    /// resources limited by `rlimits`, as `(resource, soft, hard)` tuples.
+    ///
+    /// The new zygote inherits the state of the main thread of this zygote,
+    /// and the limits override those set with [`ZygoteBuilder::rlimit()`].
+    /// It's killed once the task returns, so the limits don't apply to
+    /// the other tasks of this zygote.
+    /// ```rust
+    /// # use std::os::unix::process::ExitStatusExt;
+    /// # use zygote::{Error, Resource, Zygote};
+    /// # let zygote = Zygote::new();
+    /// let limits = [(Resource::CpuTime, 1, 2)];
+    /// let res = zygote.try_run_with_rlimits::<_, ()>(&limits, |_| loop {}, ());
+    /// let Err(Error::Died(status)) = res else { panic!() };
+    /// assert_eq!(status.signal(), Some(libc::SIGXCPU));
+    ///
+    /// // the zygote itself is unaffected
+    /// assert_eq!(zygote.run(|x: u32| x * 2, 21), 42);
+    /// ```
+    ///
+    /// # Panics
+    /// Same panic conditions as [`Zygote::run()`], and if the new zygote
+    /// can't be created or its limits can't be set.
+    /// For a non panicking version of this method see [`Zygote::try_run_with_rlimits()`].
+    #[track_caller]
+    pub fn run_with_rlimits<Args: Wire, Ret: Wire>(
+        &self,
+        rlimits: &[(Resource, u64, u64)],
+        f: fn(Args) -> Ret,
+        args: impl AsWire<Args>,
+    ) -> Ret {
+        self.try_run_with_rlimits(rlimits, f, args).unwrap()
+    }
+
+    /// Run a task in a new zygote spawned from this one, with limited resources.
+    /// Like [`Zygote::run_with_rlimits()`], but the return value is a [`Result`] that will
+    /// error if the task panics, the new zygote dies, or it can't be created.
+    #[track_caller]
+    pub fn try_run_with_rlimits<Args: Wire, Ret: for<'b> Wire>(
+        &self,
+        rlimits: &[(Resource, u64, u64)],
+        f: fn(Args) -> Ret,
+        args: impl AsWire<Args>,
+    ) -> Result<Ret, Error> {
+        let builder = rlimits
+            .iter()
+            .fold(ZygoteBuilder::new(), |builder, &(resource, soft, hard)| {
+                builder.rlimit(resource, soft, hard)
+            });
+        // a sibling, so that its exit status can be reported
+        let zygote = builder.spawn(self)?;
+        zygote.try_run(f, args)
+    }
+
+    /// Run the task `f` in the zygote process through `runner`.
+    #[track_caller]
+    fn call<Args: Wire, Ret: for<'b> Wire>(
+        &self,
+        runner: *const (),
+        f: *const (),
+        args: impl AsWire<Args>,
+    ) -> Result<Ret, Error> {
+        let (ret, _) = self.call_with_traffic(runner, f, args)?;
+        Ok(ret)
+    }
+
+    /// Like [`Zygote::call()`], but also returns what was sent and received.
+    #[track_caller]
+    fn call_with_traffic<Args: Wire, Ret: for<'b> Wire>(
+        &self,
+        runner: *const (),
+        f: *const (),
+        args: impl AsWire<Args>,
+    ) -> Result<(Ret, Traffic), Error> {
+        let task = Location::caller();
+        observer::notify(|o| o.task_started(self, task));
+        let start = Instant::now();
+        let (res, traffic) = self.send_task(runner, f, args, task);
+        observer::notify(|o| match &res {
+            Ok(_) => {
+                let metrics = TaskMetrics {
+                    duration: start.elapsed(),
+                    bytes_sent: traffic.bytes_sent,
+                    bytes_received: traffic.bytes_received,
+                    fds_sent: traffic.fds_sent,
+                    fds_received: traffic.fds_received,
+                };
+                o.task_finished(self, task, &metrics);
+            }
+            Err(Error::Wire(err)) => o.task_panicked(self, task, err),
+            Err(Error::Died(status)) => o.zygote_died(self, Some(*status)),
+            #[cfg(feature = "seccomp")]
+            Err(Error::ForbiddenSyscall(_)) => o.zygote_died(self, self.exit_status()),
+            Err(err) => o.channel_error(self, err),
+        });
+        Ok((res?, traffic))
+    }
+
+    /// Send the task `f` to the zygote, and wait for its result.
+    fn send_task<Args: Wire, Ret: for<'b> Wire>(
+        &self,
+        runner: *const (),
+        f: *const (),
+        args: impl AsWire<Args>,
+        task: &Location,
+    ) -> (Result<Ret, Error>, Traffic) {
         let mut pipe = self.0.pipe.lock().unwrap();
-        let runner = runner::<Args, Ret> as usize;
-        let f = f as usize;
-        pipe.send([f, runner])?;
-        pipe.send(args)?;
-        Ok(pipe.recv::<Result<_, WireError>>()??)
+        if self.0.moved.load(SeqCst) {
+            return (Err(Error::Moved), Traffic::default());
+        }
+        let runner = fn_offset(runner);
+        let f = fn_offset(f);
+        let before = pipe.traffic();
+        let res = pipe
+            .send([f, runner])
+            .and_then(|_| {
+                // zygotes can't be sent to themselves, see [`Transfer`]
+                DESTINATION.set(&self.0.process);
+                let sent = pipe.send(args);
+                DESTINATION.set(std::ptr::null());
+                sent
+            })
+            .and_then(|_| recv_result(&mut pipe, &self.0.process, task));
+        let traffic = pipe.traffic().since(&before);
+        let res = match res {
+            Ok(res) => res.map_err(Error::from),
+            Err(Error::Io(err))
+                if matches!(err.kind(), UnexpectedEof | BrokenPipe | ConnectionReset) =>
+            {
+                #[cfg(feature = "seccomp")]
+                if let Some(nr) = self.0.violations.as_deref().and_then(seccomp::reported) {
+                    return (
+                        Err(Error::ForbiddenSyscall(seccomp::syscall_name(nr))),
+                        traffic,
+                    );
+                }
+                Err(self.exit_status().map_or(Error::Io(err), Error::Died))
+            }
+            Err(err) => Err(err),
+        };
+        (res, traffic)
+    }
+
+    /// Get the pid of the zygote process, as seen from the pid namespace
+    /// of the calling process.
+    /// ```rust
+    /// # use zygote::Zygote;
+    /// let zygote = Zygote::new();
+    /// let pid = zygote.run(|_| std::process::id(), ());
+    /// assert_eq!(zygote.pid().unwrap(), pid);
+    /// ```
+    pub fn pid(&self) -> Result<u32, Error> {
+        Ok(self.0.process.pid()? as u32)
+    }
+
+    /// Get the pidfd of the zygote process, if the kernel supports them.
+    /// The pidfd can be used to wait for the zygote to exit, e.g., with `poll`.
+    pub fn pidfd(&self) -> Option<BorrowedFd<'_>> {
+        self.0.process.pidfd()
+    }
+
+    /// Get the memory footprint of the zygote process, and the number of
+    /// tasks it has run, not counting the call to this method.
+    ///
+    /// This can be used to check how much memory a zygote actually shares
+    /// with the process it was spawned from.
+    /// ```rust
+    /// # use zygote::Zygote;
+    /// let zygote = Zygote::new();
+    /// zygote.run(|_| {}, ());
+    /// let stats = zygote.stats().unwrap();
+    /// assert_eq!(stats.tasks, 1);
+    /// assert_eq!(stats.threads, 1);
+    /// assert!(stats.pss <= stats.rss);
+    /// ```
+    #[track_caller]
+    pub fn stats(&self) -> Result<ZygoteStats, Error> {
+        let tasks = self.try_run(|_| TASKS.get() - 1, ())?;
+        let stats = stats::footprint(self.0.process.pid()?)?;
+        Ok(ZygoteStats { tasks, ..stats })
+    }
+
+    /// Get the exit status of the zygote process, if it has exited.
+    fn exit_status(&self) -> Option<ExitStatus> {
+        // the pipe is closed before the process is done exiting
+        let process = &self.0.process;
+        process.wait_exit(Some(Duration::from_secs(1))).ok()?;
+        process.exit_status().ok()?
     }
 
     /// Create a new zygote process from within this zygote process.
@@ -253,9 +674,193 @@ impl Zygote {
     ///
     /// # Panics
     /// Same panic conditions as [`Zygote::new()`].
+    #[track_caller]
     pub fn spawn(&self) -> Zygote {
-        let inner = self.try_run(spawner, ()).unwrap();
-        Zygote(inner)
+        ZygoteBuilder::new().spawn(self).unwrap()
+    }
+
+    /// Create a new zygote process from within this zygote process.
+    /// Unlike [`Zygote::spawn()`], the new zygote process will be a child
+    /// of the current zygote.
+    ///
+    /// This is useful to build hierarchies of zygotes, e.g., to keep the
+    /// new zygote inside a PID or user namespace the current zygote lives in.
+    /// The new zygote inherits the state of the main thread of the first
+    /// zygote process, and by default it is terminated if the first zygote
+    /// dies (see [`ZygoteBuilder::parent_death_signal()`]).
+    ///
+    /// ```rust
+    /// # use zygote::Zygote;
+    /// # let zygote = Zygote::new();
+    /// # fn getppid() -> libc::pid_t { unsafe { libc::getppid() } }
+    /// # fn getpid() -> libc::pid_t { unsafe { libc::getpid() } }
+    /// let pid = zygote.run(|_| getpid(), ());
+    ///
+    /// let zygote2 = zygote.spawn_child();
+    /// let ppid2 = zygote2.run(|_| getppid(), ());
+    ///
+    /// assert_eq!(pid, ppid2); // zygote2 is a child of zygote
+    /// ```
+    ///
+    /// # Panics
+    /// Same panic conditions as [`Zygote::new()`].
+    #[track_caller]
+    pub fn spawn_child(&self) -> Zygote {
+        ZygoteBuilder::new().spawn_child(self).unwrap()
+    }
+
+    /// Make this zygote listen for connections on a unix domain socket.
+    ///
+    /// Other processes running the same executable can then connect to the
+    /// zygote using [`Zygote::connect()`], as long as they are allowed by
+    /// the `allowlist`.
+    /// Every connection is served by a new zygote process, spawned as a child
+    /// of this zygote, which inherits the state of its main thread.
+    ///
+    /// The socket is bound by the calling process. For filesystem sockets,
+    /// the socket file is not removed when the zygote exits.
+    ///
+    /// ```rust
+    /// # use zygote::{Address, Allowlist, Zygote};
+    /// # fn getppid() -> libc::pid_t { unsafe { libc::getppid() } }
+    /// # fn getpid() -> libc::pid_t { unsafe { libc::getpid() } }
+    /// # let addr = Address::abstract_name(format!("zygote-doctest-{}", std::process::id()));
+    /// let server = Zygote::new();
+    /// server.listen(addr.clone(), Allowlist::current_user()).unwrap();
+    /// let pid = server.run(|_| getpid(), ());
+    ///
+    /// let zygote = Zygote::connect(addr).unwrap();
+    /// let ppid = zygote.run(|_| getppid(), ());
+    ///
+    /// assert_eq!(pid, ppid); // the connection is served by a child of the server
+    /// ```
+    #[track_caller]
+    pub fn listen(&self, addr: impl Into<Address>, allowlist: Allowlist) -> Result<(), Error> {
+        let listener = UnixListener::bind_addr(&addr.into().to_socket_addr()?)?;
+        self.try_run(server::start_listening, (WireFd::new(listener), allowlist))
+    }
+
+    /// Connect to a zygote listening on a unix domain socket.
+    /// See [`Zygote::listen()`].
+    ///
+    /// This method fails if the zygote is not listening on `addr`, or if it
+    /// rejects the connection.
+    pub fn connect(addr: impl Into<Address>) -> Result<Zygote, Error> {
+        let (process, pipe, violations) = server::connect(&addr.into())?;
+        let mut zygote = Zygote::from_parts(process, pipe);
+        zygote.0.violations = violations;
+        Ok(zygote)
+    }
+
+    /// Get ready to send this handle to another process, e.g., as the argument
+    /// or return value of a task.
+    ///
+    /// Once the task is sent, the zygote is owned by the receiving end, where
+    /// the handle is turned back into a zygote with [`Transfer::into_zygote()`],
+    /// and the drop policy applies when that handle is dropped. If sending fails,
+    /// dropping the transfer applies the drop policy here instead.
+    /// A zygote can't be sent to itself.
+    ///
+    /// ```rust
+    /// # use zygote::{Transfer, Zygote};
+    /// # fn getpid() -> libc::pid_t { unsafe { libc::getpid() } }
+    /// let zygote1 = Zygote::new();
+    /// let zygote2 = Zygote::new();
+    /// let pid2 = zygote2.run(|_| getpid(), ());
+    ///
+    /// // zygote2 is killed when the task returns, as its handle is dropped in zygote1
+    /// let pid = zygote1.run(|z: Transfer| z.into_zygote().run(|_| getpid(), ()), zygote2.transfer());
+    /// assert_eq!(pid, pid2);
+    /// ```
+    pub fn transfer(self) -> Transfer {
+        Transfer(self)
+    }
+
+    /// Ask the zygote process to exit, and wait for it to do so.
+    ///
+    /// Unlike dropping the handle, this lets the zygote exit normally,
+    /// e.g., flushing its standard output and running any exit handlers.
+    /// If the zygote doesn't exit within `timeout`, it is killed with `SIGKILL`.
+    ///
+    /// ```rust
+    /// # use std::time::Duration;
+    /// # use zygote::Zygote;
+    /// let zygote = Zygote::new();
+    /// zygote.run(|_| print!("this will be flushed"), ());
+    ///
+    /// let status = zygote.shutdown(Duration::from_secs(1)).unwrap();
+    /// assert!(status.success());
+    /// ```
+    ///
+    /// The exit status can only be observed for zygotes that are children of the
+    /// calling process. For any other zygote, the status is reported as a success
+    /// if the zygote exited on its own, or as killed by `SIGKILL` otherwise.
+    pub fn shutdown(mut self, timeout: Duration) -> Result<ExitStatus, Error> {
+        let status = self.shutdown_impl(timeout);
+        self.0.drop_policy = DropPolicy::Detach;
+        status
+    }
+
+    fn shutdown_impl(&mut self, timeout: Duration) -> Result<ExitStatus, Error> {
+        if self.0.moved.load(SeqCst) {
+            return Err(Error::Moved);
+        }
+        let pipe = self.0.pipe.get_mut().unwrap_or_else(|err| err.into_inner());
+        // if the zygote is gone we won't be able to send the request,
+        // but we still want to collect its exit status
+        let _ = pipe.send([0, fn_offset(exit_runner as *const ())]);
+
+        let process = &self.0.process;
+        let mut status = ExitStatus::default();
+        if !process.wait_exit(Some(timeout))? {
+            self.kill_tree();
+            process.send_signal(SIGKILL)?;
+            status = ExitStatus::from_raw(SIGKILL);
+        }
+        Ok(process.wait()?.unwrap_or(status))
+    }
+
+    fn kill(&mut self) {
+        self.kill_tree();
+        let _ = self.0.process.send_signal(SIGKILL);
+        // in case we are not allowed to kill the zygote, let it know
+        // that it will not receive more tasks
+        let pipe = self.0.pipe.get_mut().unwrap_or_else(|err| err.into_inner());
+        unsafe { libc::shutdown(pipe.as_raw_fd(), libc::SHUT_RDWR) };
+        let _ = self.0.process.wait();
+    }
+
+    /// Kill the descendants of the zygote, if the zygote was configured
+    /// to do so and the kernel won't do it for us.
+    fn kill_tree(&self) {
+        if self.0.kill_tree {
+            if let Ok(pid) = self.0.process.pid() {
+                process::kill_descendants(pid);
+            }
+        }
+    }
+
+    /// Keep track of this zygote from within the current zygote process,
+    /// so that it can be reaped once it exits.
+    fn track(&self) {
+        let process = self.0.process.try_clone().unwrap();
+        add_child(process, None, None);
+    }
+
+    fn from_parts(process: Process, pipe: Pipe) -> Zygote {
+        let pipe = Mutex::new(WireFd::new(pipe));
+        let moved = Arc::new(AtomicBool::new(false));
+        let drop_policy = DropPolicy::default();
+        let kill_tree = false;
+        let violations = None;
+        Zygote(ZygoteImpl {
+            process,
+            pipe,
+            moved,
+            drop_policy,
+            kill_tree,
+            violations,
+        })
     }
 }
 
@@ -265,80 +870,130 @@ impl Default for Zygote {
     }
 }
 
-impl Drop for Zygote {
-    fn drop(&mut self) {
-        let pidfd = self.0.pidfd.as_raw_fd();
-        unsafe { libc::syscall(libc::SYS_pidfd_send_signal, pidfd, SIGKILL, 0, 0) };
-        let pidfd = self.0.pidfd.as_fd();
-        let _ = waitid(Id::PIDFd(pidfd), WaitPidFlag::WEXITED);
+/// A [`Zygote`] handle on its way to another process, e.g., as the argument
+/// or return value of a task, see [`Zygote::transfer()`].
+pub struct Transfer(Zygote);
+
+impl Transfer {
+    /// Get the handle of the zygote back, e.g., once received.
+    pub fn into_zygote(self) -> Zygote {
+        self.0
+    }
+}
+
+impl Serialize for Transfer {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let zygote = &self.0 .0;
+        let destination = DESTINATION.get();
+        // safety: the destination is only set while a task is being sent to it
+        if !destination.is_null() {
+            let destination = unsafe { &*destination }.pid();
+            if let (Ok(pid), Ok(destination)) = (zygote.process.pid(), destination) {
+                if pid == destination {
+                    return Err(S::Error::custom("a zygote can't be sent to itself"));
+                }
+            }
+        }
+        let pipe = zygote.pipe.lock().unwrap_or_else(|err| err.into_inner());
+        // the receiving end only owns the zygote once the handle is sent
+        TRANSFERS.with_borrow_mut(|transfers| transfers.push(Arc::clone(&zygote.moved)));
+        let (drop_policy, kill_tree) = (zygote.drop_policy, zygote.kill_tree);
+        let violations = &zygote.violations;
+        let parts = (&zygote.process, &*pipe, drop_policy, kill_tree, violations);
+        Serialize::serialize(&parts, serializer)
     }
 }
 
-fn clone3_or_clone(flags: i32, exit_signal: i32) -> io::Result<Option<OwnedFd>> {
-    #[cfg(feature = "clone3")]
-    if let Ok(res) = clone3(flags, exit_signal) {
-        return Ok(res);
+impl<'a> Deserialize<'a> for Transfer {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'a>,
+    {
+        let (process, pipe, drop_policy, kill_tree, violations): (_, WireFd<Pipe>, _, _, _) =
+            Deserialize::deserialize(deserializer)?;
+        let mut zygote = Zygote::from_parts(process, pipe.into_inner());
+        zygote.0.drop_policy = drop_policy;
+        zygote.0.kill_tree = kill_tree;
+        zygote.0.violations = violations;
+        Ok(Transfer(zygote))
     }
-    clone(flags, exit_signal)
 }
 
-#[cfg(feature = "clone3")]
-fn clone3(flags: i32, exit_signal: i32) -> io::Result<Option<OwnedFd>> {
-    let mut args = [flags as u64, 0, 0, 0, exit_signal as u64, 0, 0, 0, 0, 0, 0];
-    let args_ptr = std::ptr::from_mut(&mut args);
-    let args_size = std::mem::size_of_val(&args);
-    let res = unsafe { libc::syscall(libc::SYS_clone3, args_ptr, args_size) };
-    match res {
-        0 => Ok(None),
-        pid @ 1.. => {
-            let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
-            let pidfd = unsafe { OwnedFd::from_raw_fd(pidfd as _) };
-            Ok(Some(pidfd))
+/// Mark the zygotes serialized since the last call as owned by the receiving
+/// end, if `sent`, this is called once the data they were serialized in is sent.
+pub(crate) fn commit_transfers(sent: bool) {
+    for moved in TRANSFERS.take() {
+        if sent {
+            moved.store(true, SeqCst);
         }
-        -1 => Err(io::Error::last_os_error()),
-        _ => Err(io::Error::other("unknown")),
     }
 }
 
-fn clone(flags: i32, exit_signal: i32) -> io::Result<Option<OwnedFd>> {
-    // For sjlj information see: https://llvm.org/docs/ExceptionHandling.html#llvm-eh-sjlj-setjmp
-    let mut jmp_buf = [0u16; 128];
-    extern "C" {
-        #[link_name = "setjmp"]
-        fn setjmp(buf: *mut u16) -> i32;
-        #[link_name = "longjmp"]
-        fn longjmp(buf: *mut u16, val: i32) -> !;
-    }
-    let res = unsafe { setjmp(jmp_buf.as_mut_ptr()) };
-    match res {
-        0 => {}
-        1 => return Ok(None),
-        _ => return Err(io::Error::other("unknown")),
-    }
-    let f = Box::new(|| {
-        unsafe { longjmp(jmp_buf.as_mut_ptr(), 1) };
-    });
-    // we need a stack just big enough to reach the longjmp
-    let mut stack = [0; 1024];
-    let pid = unsafe {
-        nix::sched::clone(
-            f,
-            &mut stack,
-            CloneFlags::from_bits(flags).unwrap(),
-            Some(exit_signal),
-        )
-    }?;
-    let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid.as_raw(), 0) };
-    let pidfd = unsafe { OwnedFd::from_raw_fd(pidfd as _) };
-    return Ok(Some(pidfd));
+impl Drop for Zygote {
+    fn drop(&mut self) {
+        if self.0.moved.load(SeqCst) {
+            // the zygote is owned by someone else
+            return;
+        }
+        match self.0.drop_policy {
+            DropPolicy::Kill => self.kill(),
+            DropPolicy::Graceful(timeout) => {
+                let _ = self.shutdown_impl(timeout);
+            }
+            DropPolicy::Detach => {}
+        }
+    }
 }
 
-fn spawner(_: ()) -> ZygoteImpl {
-    let zygote = Zygote::new_impl(true);
-    unsafe { transmute(zygote) }
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Relation {
+    Child,
+    Sibling,
+    Nested,
+}
+
+#[cfg_attr(not(feature = "seccomp"), allow(unused_variables))]
+fn serve_connection(mut pipe: Pipe) {
+    let Ok(parent) = Process::open(unsafe { libc::getpid() }) else {
+        return;
+    };
+    match clone3_or_clone(0, SIGCHLD, None) {
+        Ok(None) => {
+            process::die_with_parent(&parent, SIGKILL);
+            drop(parent);
+            // the broker is not meant for whoever connected to us,
+            // and violations are reported to them rather than to our creator
+            broker::set_current(None);
+            let violations = match violations_pipe(None) {
+                Ok(Some((violations, report))) => {
+                    #[cfg(feature = "seccomp")]
+                    seccomp::report_to(report);
+                    Some(violations)
+                }
+                Ok(None) => None,
+                Err(_) => std::process::exit(1),
+            };
+            if server::handshake(&mut pipe, violations).is_err() {
+                std::process::exit(1);
+            }
+            zygote_start(pipe);
+        }
+        Ok(Some(child)) => add_child(child, None, None),
+        Err(_) => {}
+    }
 }
 
 fn zygote_start(pipe: Pipe) -> ! {
+    // the state of the zygote we were spawned from is not ours to keep
+    CHILDREN.take();
+    server::LISTENERS.take();
+    TASKS.take();
+    if let Some(fd) = PIPE_FD.take() {
+        unsafe { libc::close(fd) };
+    }
     match zygote_main(pipe) {
         Ok(()) => std::process::exit(0),
         Err(Error::Io(err)) if err.kind() == UnexpectedEof || err.kind() == BrokenPipe => {
@@ -352,6 +1007,40 @@ fn zygote_start(pipe: Pipe) -> ! {
 
 thread_local! {
     static PANIC_ERROR: Cell<Option<WireError>> = const { Cell::new(None) };
+    static PIPE_FD: Cell<Option<RawFd>> = const { Cell::new(None) };
+    static CHILDREN: RefCell<Vec<ZygoteChild>> = const { RefCell::new(vec![]) };
+    static KILL_TREE: Cell<bool> = const { Cell::new(false) };
+    static TASKS: Cell<u64> = const { Cell::new(0) };
+    static TRANSFERS: RefCell<Vec<Arc<AtomicBool>>> = const { RefCell::new(vec![]) };
+    static DESTINATION: Cell<*const Process> = const { Cell::new(std::ptr::null()) };
+}
+
+/// Create the pipe a new zygote reports seccomp violations on, if it's going to
+/// have a seccomp policy, either from its `builder` or from the calling process.
+#[cfg_attr(not(feature = "seccomp"), allow(unused_variables))]
+fn violations_pipe(builder: Option<&ZygoteBuilder>) -> io::Result<Option<(File, OwnedFd)>> {
+    #[cfg(feature = "seccomp")]
+    if seccomp::installed() || builder.is_some_and(|builder| builder.seccomp.is_some()) {
+        return seccomp::report_pipe().map(Some);
+    }
+    Ok(None)
+}
+
+/// A child of the zygote, along with where to report its exit status,
+/// and where to serve the requests to signal it from, see [`add_child()`].
+type ZygoteChild = (Process, Option<File>, Option<OwnedFd>);
+
+/// Keep track of a `child` of the zygote, so that it's reaped when it exits.
+/// Its exit status is then written to `status`, if any. Until then, requests
+/// to signal it are served from `signals`, if any, see [`command::serve_signal()`].
+fn add_child(child: Process, status: Option<File>, signals: Option<OwnedFd>) {
+    CHILDREN.with_borrow_mut(|children| children.push((child, status, signals)));
+}
+
+fn report_exit(status: Option<&File>, exit_status: io::Result<Option<ExitStatus>>) {
+    if let (Some(mut status), Ok(Some(exit_status))) = (status, exit_status) {
+        let _ = status.write_all(&exit_status.into_raw().to_ne_bytes());
+    }
 }
 
 fn set_panic(error: WireError) {
@@ -373,24 +1062,217 @@ fn zygote_main(mut pipe: Pipe) -> Result<(), Error> {
         panic_hook(info);
     }));
 
+    PIPE_FD.set(Some(pipe.as_fd().as_raw_fd()));
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    logging::init();
+
     loop {
+        wait_request(&pipe)?;
         let [f, runner] = pipe.recv::<[usize; 2]>()?;
-        let runner: fn(&mut Pipe, usize) -> Result<(), Error> = unsafe { transmute(runner) };
-        runner(&mut pipe, f)?;
+        TASKS.set(TASKS.get() + 1);
+        let runner: fn(&mut Pipe, usize) -> Result<(), Error> =
+            unsafe { transmute(fn_from_offset(runner)) };
+        runner(&mut pipe, fn_from_offset(f) as usize)?;
     }
 }
 
+/// Receive the result of a task, emitting any record the task
+/// forwards in the meantime.
+#[cfg_attr(
+    not(any(feature = "log", feature = "tracing")),
+    allow(unused_variables, unused_mut)
+)]
+fn recv_result<Ret: Wire>(
+    pipe: &mut Pipe,
+    zygote: &Process,
+    task: &Location,
+) -> Result<Result<Ret, WireError>, Error> {
+    let mut res = pipe.recv_delayed()?;
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    while res.is::<logging::Record>() {
+        logging::emit(res.deserialize()?, zygote.pid().ok(), task);
+        res = pipe.recv_delayed()?;
+    }
+    res.deserialize()
+}
+
+// Functions are sent to the zygote as offsets relative to `zygote_main`,
+// so that they remain valid in any process running the same executable,
+// e.g., when connecting to a zygote through a socket.
+fn fn_offset(f: *const ()) -> usize {
+    (f as usize).wrapping_sub(zygote_main as *const () as usize)
+}
+
+fn fn_from_offset(offset: usize) -> *const () {
+    (zygote_main as *const () as usize).wrapping_add(offset) as *const ()
+}
+
+/// Wait for the next request on the pipe, reaping any spawned children
+/// that exit and serving incoming connections in the meantime.
+///
+/// If the other end of the pipe is closed while the zygote is listening for
+/// connections, the zygote keeps serving connections.
+fn wait_request(pipe: &Pipe) -> Result<(), Error> {
+    let mut pipe = Some(pipe);
+    loop {
+        let listeners = server::listener_fds();
+        let (children, signals, pid_only) = CHILDREN.with_borrow(|children| {
+            let pidfds = children.iter().filter_map(|(child, ..)| child.pidfd());
+            let signals = children.iter().filter_map(|(.., signals)| signals.as_ref());
+            let pid_only = children.iter().any(|(child, ..)| child.pidfd().is_none());
+            let pidfds: Vec<RawFd> = pidfds.map(|fd| fd.as_raw_fd()).collect();
+            let signals: Vec<RawFd> = signals.map(|fd| fd.as_raw_fd()).collect();
+            (pidfds, signals, pid_only)
+        });
+
+        let mut fds = vec![];
+        for fd in pipe
+            .iter()
+            .map(|p| p.as_fd().as_raw_fd())
+            .chain(listeners.iter().copied())
+            .chain(children.iter().copied())
+            .chain(signals.iter().copied())
+        {
+            let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+            fds.push(PollFd::new(fd, PollFlags::POLLIN));
+        }
+        // without a pidfd, we can't poll for the exit of a child, check every now and then
+        let timeout = match pid_only {
+            true => PollTimeout::try_from(process::POLL_INTERVAL).unwrap(),
+            false => PollTimeout::NONE,
+        };
+        match poll(&mut fds, timeout) {
+            Ok(_) | Err(Errno::EINTR) => {}
+            Err(err) => return Err(io::Error::from(err).into()),
+        }
+        let revents: Vec<_> = fds
+            .iter()
+            .map(|fd| fd.revents().unwrap_or(PollFlags::all()))
+            .collect();
+        let (pipe_revents, revents) = revents.split_at(pipe.iter().len());
+        let ready: Vec<bool> = revents.iter().map(|r| !r.is_empty()).collect();
+        let (listeners_ready, ready) = ready.split_at(listeners.len());
+        let (children_ready, signals_ready) = ready.split_at(children.len());
+
+        let mut children_ready = children_ready.iter();
+        let mut signals_ready = signals_ready.iter();
+        CHILDREN.with_borrow_mut(|children| {
+            // serve the requests to signal children before reaping them
+            for (child, _, signals) in children.iter_mut().filter(|(.., s)| s.is_some()) {
+                let ready = *signals_ready.next().unwrap();
+                if ready && !command::serve_signal(child, signals.as_ref().unwrap()) {
+                    *signals = None;
+                }
+            }
+            children.retain(|(child, status, _)| match child.pidfd() {
+                Some(_) => {
+                    let ready = *children_ready.next().unwrap();
+                    if ready {
+                        report_exit(status.as_ref(), child.reap());
+                    }
+                    !ready
+                }
+                // without a pidfd, we reap the child whenever we wake up
+                None => match child.try_reap() {
+                    Ok(None) => true,
+                    exit_status => {
+                        report_exit(status.as_ref(), exit_status);
+                        false
+                    }
+                },
+            })
+        });
+
+        for (n, _) in listeners_ready.iter().enumerate().filter(|(_, r)| **r) {
+            if let Some(conn) = server::accept(n) {
+                serve_connection(conn);
+            }
+        }
+
+        match (pipe, pipe_revents.first()) {
+            (Some(p), Some(r)) if r.contains(PollFlags::POLLHUP) && !listeners.is_empty() => {
+                // keep processing any request sent before the pipe was closed
+                let mut pending: libc::c_int = 0;
+                unsafe { libc::ioctl(p.as_fd().as_raw_fd(), libc::FIONREAD, &mut pending) };
+                if pending > 0 {
+                    return Ok(());
+                }
+                pipe = None;
+            }
+            (_, Some(r)) if !r.is_empty() => return Ok(()),
+            _ => {}
+        }
+    }
+}
+
+fn exit_runner(_pipe: &mut Pipe, _f: usize) -> Result<(), Error> {
+    if KILL_TREE.get() {
+        process::kill_descendants(unsafe { libc::getpid() });
+    }
+    std::process::exit(0);
+}
+
 fn runner<Args: Wire, Ret: Wire>(pipe: &mut Pipe, f: usize) -> Result<(), Error>
 where
     Result<Ret, WireError>: Wire,
+{
+    run_task::<Args, Ret, _>(pipe, f, |f, args| Ok(f(args)))
+}
+
+fn captured_runner<Args: Wire, Ret: Wire>(pipe: &mut Pipe, f: usize) -> Result<(), Error>
+where
+    Result<(Ret, Output), WireError>: Wire,
+{
+    run_task::<Args, Ret, _>(pipe, f, |f, args| capture::capture(|| f(args)))
+}
+
+fn stats_runner<Args: Wire, Ret: Wire>(pipe: &mut Pipe, f: usize) -> Result<(), Error>
+where
+    Result<(Ret, Stats), WireError>: Wire,
+{
+    run_task::<Args, Ret, _>(pipe, f, |f, args| stats::measure(|| f(args)))
+}
+
+/// Receive the arguments of the task `f`, and send back what `wrap`
+/// returns calling it, or the panic of the task.
+fn run_task<Args: Wire, Ret, Out>(
+    pipe: &mut Pipe,
+    f: usize,
+    wrap: impl FnOnce(fn(Args) -> Ret, Args) -> Result<Out, WireError> + UnwindSafe,
+) -> Result<(), Error>
+where
+    Result<Out, WireError>: Wire,
 {
     let f: fn(Args) -> Ret = unsafe { transmute(f) };
     let args = pipe.recv_delayed()?;
-    let res = catch_unwind(move || -> Result<Ret, WireError> {
-        let args = args.deserialize::<Args>()?;
-        Ok(f(args))
-    })
-    .unwrap_or_else(|_| Err(take_panic()));
+    let res = {
+        // records are only forwarded while the task runs
+        #[cfg(any(feature = "log", feature = "tracing"))]
+        let _forward = logging::Forward::new(pipe);
+        catch_unwind(move || wrap(f, args.deserialize::<Args>()?))
+            .unwrap_or_else(|_| Err(take_panic()))
+    };
     pipe.send(res)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::os::fd::AsFd as _;
+
+    use crate::pipe::Pipe;
+    use crate::{Error, Transfer, Zygote};
+
+    #[test]
+    fn send_to_itself() {
+        let zygote = Zygote::new();
+        // another handle of the same zygote
+        let process = zygote.0.process.try_clone().unwrap();
+        let pipe = zygote.0.pipe.lock().unwrap().as_fd().try_clone_to_owned();
+        let pipe = Pipe::from(pipe.unwrap());
+        let handle = Zygote::from_parts(process, pipe).transfer();
+
+        let res = zygote.try_run(|_: Transfer| {}, handle);
+        assert!(matches!(res, Err(Error::Encode(_))), "{res:?}");
+    }
+}
@@ -0,0 +1,422 @@
+//! Forwarding of `log` records and `tracing` events from the zygote to the
+//! process running the task.
+//!
+//! While a task runs, records emitted by the zygote thread running it are
+//! sent over the task pipe, ahead of the task result, and the caller emits
+//! them again with the pid of the zygote and the location of the task attached.
+//! Records emitted anywhere else in the zygote are not forwarded.
+
+use std::cell::Cell;
+use std::mem::ManuallyDrop;
+use std::os::fd::{AsFd as _, AsRawFd as _, FromRawFd as _, OwnedFd, RawFd};
+use std::panic::Location;
+
+use serde::{Deserialize, Serialize};
+
+use crate::pipe::Pipe;
+
+thread_local! {
+    /// The pipe of the running task, and the process it belongs to.
+    static FORWARD: Cell<Option<(RawFd, libc::pid_t)>> = const { Cell::new(None) };
+}
+
+/// A log record, as sent from the zygote.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Record {
+    source: Source,
+    /// From 1 (error) to 5 (trace).
+    level: u8,
+    target: String,
+    message: String,
+    module_path: Option<String>,
+    file: Option<String>,
+    line: Option<u32>,
+    /// The spans the event was emitted in within the zygote, outermost first.
+    spans: Vec<Span>,
+}
+
+/// A span a record was emitted in, as sent from the zygote.
+#[derive(Serialize, Deserialize, Clone)]
+struct Span {
+    name: String,
+    /// Same as [`Record::level`].
+    level: u8,
+    /// The fields of the span, formatted like the message of an event.
+    fields: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+enum Source {
+    #[cfg(feature = "log")]
+    Log,
+    #[cfg(feature = "tracing")]
+    Tracing,
+}
+
+/// Forwards the records emitted by the calling thread over a pipe while alive.
+pub(crate) struct Forward(());
+
+impl Forward {
+    pub(crate) fn new(pipe: &Pipe) -> Self {
+        let pid = unsafe { libc::getpid() };
+        FORWARD.set(Some((pipe.as_fd().as_raw_fd(), pid)));
+        Forward(())
+    }
+}
+
+impl Drop for Forward {
+    fn drop(&mut self) {
+        FORWARD.set(None);
+    }
+}
+
+/// Install the forwarders in the zygote.
+pub(crate) fn init() {
+    #[cfg(feature = "log")]
+    {
+        // if the application installed its logger before creating the zygote,
+        // the zygote keeps it, and it only forwards if it's a `LogForwarder`
+        if log::set_logger(&LogForwarder(NoLogger)).is_ok() {
+            // the calling process filters the records it gets
+            log::set_max_level(log::LevelFilter::Trace);
+        }
+    }
+    #[cfg(feature = "tracing")]
+    {
+        // the global subscriber can only be set once, but this thread is
+        // the only one forwarding anyway
+        let dispatch = tracing::Dispatch::new(trace::Forwarder::default());
+        std::mem::forget(tracing::dispatcher::set_default(&dispatch));
+    }
+}
+
+/// Send `record` over the pipe of the running task, if any.
+/// Returns `false` if the record can't be forwarded.
+fn forward(record: impl FnOnce() -> Record) -> bool {
+    let Some((fd, pid)) = FORWARD.get() else {
+        return false;
+    };
+    // a process forked by the task has no business writing to the pipe
+    if pid != unsafe { libc::getpid() } {
+        return false;
+    }
+    // avoid forwarding the records emitted while forwarding
+    FORWARD.set(None);
+    let mut pipe = ManuallyDrop::new(Pipe::from(unsafe { OwnedFd::from_raw_fd(fd) }));
+    let _ = pipe.send(record());
+    FORWARD.set(Some((fd, pid)));
+    true
+}
+
+/// Emit a `record` forwarded from the zygote `pid`, running the task at `task`.
+pub(crate) fn emit(record: Record, pid: Option<libc::pid_t>, task: &Location) {
+    let task = task.to_string();
+    match record.source {
+        #[cfg(feature = "log")]
+        Source::Log => {
+            let level = match record.level {
+                1 => log::Level::Error,
+                2 => log::Level::Warn,
+                3 => log::Level::Info,
+                4 => log::Level::Debug,
+                _ => log::Level::Trace,
+            };
+            if level > log::max_level() {
+                return;
+            }
+            use log::kv::ToValue as _;
+            let kvs = [
+                ("zygote.pid", pid.to_value()),
+                ("zygote.task", task.to_value()),
+            ];
+            log::logger().log(
+                &log::Record::builder()
+                    .level(level)
+                    .target(&record.target)
+                    .args(format_args!("{}", record.message))
+                    .module_path(record.module_path.as_deref())
+                    .file(record.file.as_deref())
+                    .line(record.line)
+                    .key_values(&kvs)
+                    .build(),
+            );
+        }
+        #[cfg(feature = "tracing")]
+        Source::Tracing => {
+            // rebuild the spans of the zygote within the current span of the caller
+            let spans: Vec<_> = record.spans.iter().map(trace::enter).collect();
+            macro_rules! event {
+                ($level:expr) => {
+                    tracing::event!(
+                        target: "zygote",
+                        $level,
+                        zygote.pid = pid,
+                        zygote.task = task,
+                        zygote.target = record.target,
+                        "{}",
+                        record.message,
+                    )
+                };
+            }
+            match record.level {
+                1 => event!(tracing::Level::ERROR),
+                2 => event!(tracing::Level::WARN),
+                3 => event!(tracing::Level::INFO),
+                4 => event!(tracing::Level::DEBUG),
+                _ => event!(tracing::Level::TRACE),
+            }
+            // exit the spans innermost first
+            spans.into_iter().rev().for_each(drop);
+        }
+    }
+}
+
+/// A [`log::Log`] implementation that forwards the records emitted by tasks
+/// running in a zygote to the process that runs the task, and passes any
+/// other record to the wrapped logger.
+///
+/// A zygote installs its own forwarding logger, but a process can only have
+/// one logger. If the application installs its logger before creating
+/// the zygote, it needs to wrap it with a `LogForwarder`.
+///
+/// ```rust
+/// # use zygote::{LogForwarder, Zygote};
+/// struct Stderr;
+///
+/// impl log::Log for Stderr {
+///     fn enabled(&self, _: &log::Metadata) -> bool { true }
+///     fn log(&self, record: &log::Record) { eprintln!("{}", record.args()) }
+///     fn flush(&self) {}
+/// }
+///
+/// log::set_boxed_logger(Box::new(LogForwarder::new(Stderr))).unwrap();
+/// log::set_max_level(log::LevelFilter::Info);
+///
+/// // this is logged by the `Stderr` logger of the calling process
+/// Zygote::new().run(|_| log::info!("hello from the zygote"), ());
+/// ```
+#[cfg(feature = "log")]
+pub struct LogForwarder<L>(L);
+
+#[cfg(feature = "log")]
+impl<L: log::Log> LogForwarder<L> {
+    /// Wrap `logger`, which handles the records that are not forwarded.
+    pub fn new(logger: L) -> Self {
+        Self(logger)
+    }
+}
+
+#[cfg(feature = "log")]
+impl<L: log::Log> log::Log for LogForwarder<L> {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        FORWARD.get().is_some() || self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        let forwarded = forward(|| Record {
+            source: Source::Log,
+            level: record.level() as u8,
+            target: record.target().to_owned(),
+            message: record.args().to_string(),
+            module_path: record.module_path().map(str::to_owned),
+            file: record.file().map(str::to_owned),
+            line: record.line(),
+            spans: vec![],
+        });
+        if !forwarded {
+            self.0.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.0.flush();
+    }
+}
+
+#[cfg(feature = "log")]
+struct NoLogger;
+
+#[cfg(feature = "log")]
+impl log::Log for NoLogger {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        false
+    }
+
+    fn log(&self, _: &log::Record) {}
+
+    fn flush(&self) {}
+}
+
+#[cfg(feature = "tracing")]
+mod trace {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::fmt::{Debug, Write as _};
+    use std::sync::atomic::AtomicU64;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::sync::Mutex;
+
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record as Values};
+    use tracing::{Event, Level, Metadata, Subscriber};
+
+    use super::{forward, Record, Source, Span};
+
+    thread_local! {
+        static STACK: RefCell<Vec<u64>> = const { RefCell::new(vec![]) };
+    }
+
+    /// A subscriber that forwards events, keeping track of the spans
+    /// they are emitted in.
+    #[derive(Default)]
+    pub(super) struct Forwarder {
+        next_id: AtomicU64,
+        /// Each span, and its reference count.
+        spans: Mutex<HashMap<u64, (Span, usize)>>,
+    }
+
+    impl Subscriber for Forwarder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let id = self.next_id.fetch_add(1, Relaxed) + 1;
+            let mut fields = Message::default();
+            span.record(&mut fields);
+            let span = Span {
+                name: span.metadata().name().to_owned(),
+                level: level(span.metadata().level()),
+                fields: fields.0,
+            };
+            self.spans.lock().unwrap().insert(id, (span, 1));
+            Id::from_u64(id)
+        }
+
+        fn record(&self, span: &Id, values: &Values<'_>) {
+            if let Some((span, _)) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+                let mut fields = Message(std::mem::take(&mut span.fields));
+                values.record(&mut fields);
+                span.fields = fields.0;
+            }
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            forward(|| {
+                let metadata = event.metadata();
+                let mut message = Message::default();
+                event.record(&mut message);
+                let spans = self.spans.lock().unwrap();
+                let spans = STACK.with_borrow(|stack| {
+                    let entered = stack.iter().filter_map(|id| spans.get(id));
+                    entered.map(|(span, _)| span.clone()).collect()
+                });
+                Record {
+                    source: Source::Tracing,
+                    level: level(metadata.level()),
+                    target: metadata.target().to_owned(),
+                    message: message.0,
+                    module_path: metadata.module_path().map(str::to_owned),
+                    file: metadata.file().map(str::to_owned),
+                    line: metadata.line(),
+                    spans,
+                }
+            });
+        }
+
+        fn enter(&self, span: &Id) {
+            STACK.with_borrow_mut(|stack| stack.push(span.into_u64()));
+        }
+
+        fn exit(&self, span: &Id) {
+            STACK.with_borrow_mut(|stack| {
+                if let Some(n) = stack.iter().rposition(|id| *id == span.into_u64()) {
+                    stack.remove(n);
+                }
+            });
+        }
+
+        fn clone_span(&self, span: &Id) -> Id {
+            if let Some((_, refs)) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+                *refs += 1;
+            }
+            span.clone()
+        }
+
+        fn try_close(&self, span: Id) -> bool {
+            let mut spans = self.spans.lock().unwrap();
+            let Some((_, refs)) = spans.get_mut(&span.into_u64()) else {
+                return false;
+            };
+            *refs -= 1;
+            if *refs == 0 {
+                spans.remove(&span.into_u64());
+                return true;
+            }
+            false
+        }
+    }
+
+    /// Create and enter a span in the calling process, for a `span` of the zygote.
+    pub(super) fn enter(span: &Span) -> tracing::span::EnteredSpan {
+        macro_rules! span {
+            ($level:expr) => {
+                tracing::span!(
+                    target: "zygote",
+                    $level,
+                    "zygote",
+                    name = span.name,
+                    fields = tracing::field::Empty,
+                )
+            };
+        }
+        let entered = match span.level {
+            1 => span!(Level::ERROR),
+            2 => span!(Level::WARN),
+            3 => span!(Level::INFO),
+            4 => span!(Level::DEBUG),
+            _ => span!(Level::TRACE),
+        };
+        let fields = span.fields.trim_start();
+        if !fields.is_empty() {
+            entered.record("fields", fields);
+        }
+        entered.entered()
+    }
+
+    fn level(level: &Level) -> u8 {
+        match *level {
+            Level::ERROR => 1,
+            Level::WARN => 2,
+            Level::INFO => 3,
+            Level::DEBUG => 4,
+            Level::TRACE => 5,
+        }
+    }
+
+    /// The message of an event, followed by its other fields.
+    #[derive(Default)]
+    struct Message(String);
+
+    impl Visit for Message {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            match field.name() {
+                "message" => self.0.insert_str(0, value),
+                name => {
+                    let _ = write!(self.0, " {name}={value:?}");
+                }
+            }
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            match field.name() {
+                "message" => self.0.insert_str(0, &format!("{value:?}")),
+                name => {
+                    let _ = write!(self.0, " {name}={value:?}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use std::fs::{DirBuilder, File, OpenOptions};
+use std::os::fd::{AsRawFd as _, OwnedFd};
+use std::os::unix::fs::{DirBuilderExt as _, OpenOptionsExt as _};
+use std::path::{Path, PathBuf};
+
+use nix::mount::{mount, umount2, MntFlags, MsFlags};
+use nix::unistd::pivot_root;
+use serde::{Deserialize, Serialize};
+
+use crate::WireError;
+
+/// A mount to set up in the new root filesystem of a zygote.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+enum Mount {
+    Bind {
+        source: PathBuf,
+        target: PathBuf,
+        read_only: bool,
+    },
+    Tmpfs(PathBuf),
+    Proc(PathBuf),
+}
+
+/// The root filesystem of a zygote, see [`ZygoteBuilder::mounts()`](crate::ZygoteBuilder::mounts).
+///
+/// The zygote starts with an empty root filesystem, and only sees the
+/// mounts in the spec. Mounts are set up in order, so a mount hides
+/// any earlier one beneath its target.
+/// Once set up, the root filesystem itself is made read-only.
+///
+/// ```rust,no_run
+/// # use zygote::{Mounts, Zygote};
+/// let zygote = Zygote::builder()
+///     .user_namespace(true)
+///     .mounts(
+///         Mounts::new()
+///             .read_only("/usr")
+///             .read_only("/lib")
+///             .tmpfs("/tmp")
+///             .proc("/proc"),
+///     )
+///     .build()
+///     .unwrap();
+///
+/// let has_home = zygote.run(|_| std::path::Path::new("/home").exists(), ());
+/// assert!(!has_home);
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Mounts {
+    mounts: Vec<Mount>,
+}
+
+impl Mounts {
+    /// Create a spec for an empty root filesystem.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bind mount the host `source` file or directory read-only at `target`.
+    pub fn bind(mut self, source: impl Into<PathBuf>, target: impl Into<PathBuf>) -> Self {
+        let (source, target) = (source.into(), target.into());
+        let read_only = true;
+        self.mounts.push(Mount::Bind {
+            source,
+            target,
+            read_only,
+        });
+        self
+    }
+
+    /// Bind mount the host `path` read-only at the same location.
+    pub fn read_only(self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        self.bind(path.clone(), path)
+    }
+
+    /// Bind mount the host `path` at the same location, allowing changes
+    /// to the files beneath it.
+    pub fn read_write(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        self.mounts.push(Mount::Bind {
+            source: path.clone(),
+            target: path,
+            read_only: false,
+        });
+        self
+    }
+
+    /// Mount a new, private, tmpfs at `target`, e.g., `/tmp`.
+    pub fn tmpfs(mut self, target: impl Into<PathBuf>) -> Self {
+        self.mounts.push(Mount::Tmpfs(target.into()));
+        self
+    }
+
+    /// Mount a new procfs at `target`, e.g., `/proc`.
+    /// This creates the zygote in a new PID namespace.
+    pub fn proc(mut self, target: impl Into<PathBuf>) -> Self {
+        self.mounts.push(Mount::Proc(target.into()));
+        self
+    }
+
+    /// Check if the spec mounts a procfs.
+    pub(crate) fn has_proc(&self) -> bool {
+        self.mounts.iter().any(|m| matches!(m, Mount::Proc(_)))
+    }
+
+    /// Set up the root filesystem of the calling process, which
+    /// must be in a new mount namespace.
+    pub(crate) fn apply(&self) -> Result<(), WireError> {
+        let none = None::<&str>;
+        mount(none, "/", none, MsFlags::MS_REC | MsFlags::MS_PRIVATE, none)
+            .map_err(|err| WireError::from(err).context("make the mounts private"))?;
+
+        // open the bind mount sources before the host filesystem is hidden
+        let sources = self
+            .mounts
+            .iter()
+            .map(|m| match m {
+                Mount::Bind { source, .. } => open_path(source)
+                    .map(Some)
+                    .map_err(|err| err.context(format_args!("open {source:?}"))),
+                _ => Ok(None),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // any existing directory will do, it's hidden by the new root
+        let root = Path::new("/tmp");
+        mount(
+            Some("tmpfs"),
+            root,
+            Some("tmpfs"),
+            MsFlags::empty(),
+            Some("mode=0755"),
+        )
+        .map_err(|err| WireError::from(err).context("mount the new root"))?;
+
+        for (m, source) in self.mounts.iter().zip(sources) {
+            match (m, source) {
+                (
+                    Mount::Bind {
+                        source,
+                        target,
+                        read_only,
+                    },
+                    Some(fd),
+                ) => {
+                    let what = format_args!("bind mount {source:?}");
+                    bind(fd, &root.join(relative(target)), *read_only)
+                        .map_err(|err| err.context(what))?;
+                }
+                (Mount::Tmpfs(target), _) => {
+                    let target = create_dir(&root.join(relative(target)))?;
+                    let flags = MsFlags::MS_NOSUID | MsFlags::MS_NODEV;
+                    mount(
+                        Some("tmpfs"),
+                        &target,
+                        Some("tmpfs"),
+                        flags,
+                        Some("mode=1777"),
+                    )
+                    .map_err(|err| WireError::from(err).context("mount tmpfs"))?;
+                }
+                (Mount::Proc(target), _) => {
+                    let target = create_dir(&root.join(relative(target)))?;
+                    let flags = MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC;
+                    mount(Some("proc"), &target, Some("proc"), flags, none)
+                        .map_err(|err| WireError::from(err).context("mount procfs"))?;
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        // stack the old root on top of the new one, and detach it
+        std::env::set_current_dir(root)?;
+        pivot_root(".", ".").map_err(|err| WireError::from(err).context("pivot_root"))?;
+        umount2(".", MntFlags::MNT_DETACH)
+            .map_err(|err| WireError::from(err).context("unmount the old root"))?;
+        std::env::set_current_dir("/")?;
+
+        set_read_only(&open_path(Path::new("/"))?, false)
+            .map_err(|err| err.context("make the root read-only"))?;
+        Ok(())
+    }
+}
+
+fn relative(path: &Path) -> &Path {
+    path.strip_prefix("/").unwrap_or(path)
+}
+
+fn open_path(path: &Path) -> Result<OwnedFd, WireError> {
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_PATH)
+        .open(path)?;
+    Ok(file.into())
+}
+
+fn create_dir(path: &Path) -> Result<PathBuf, WireError> {
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o755)
+        .create(path)
+        .map_err(|err| WireError::from(err).context(format_args!("create {path:?}")))?;
+    Ok(path.to_owned())
+}
+
+fn bind(source: OwnedFd, target: &Path, read_only: bool) -> Result<(), WireError> {
+    // the mount point must be of the same kind as the source
+    if File::from(source.try_clone()?).metadata()?.is_dir() {
+        create_dir(target)?;
+    } else {
+        create_dir(target.parent().unwrap())?;
+        File::create(target)?;
+    }
+    let source = format!("/proc/self/fd/{}", source.as_raw_fd());
+    let none = None::<&str>;
+    let flags = MsFlags::MS_BIND | MsFlags::MS_REC;
+    mount(Some(source.as_str()), target, none, flags, none)?;
+    if read_only {
+        set_read_only(&open_path(target)?, true)?;
+    }
+    Ok(())
+}
+
+#[repr(C)]
+struct MountAttr {
+    attr_set: u64,
+    attr_clr: u64,
+    propagation: u64,
+    userns_fd: u64,
+}
+
+const MOUNT_ATTR_RDONLY: u64 = 1;
+
+/// Make `mount` read-only, and all its submounts if `recursive`.
+fn set_read_only(mount: &OwnedFd, recursive: bool) -> Result<(), WireError> {
+    // unlike a remount, this keeps the flags that are locked in a user namespace
+    let attr = MountAttr {
+        attr_set: MOUNT_ATTR_RDONLY,
+        attr_clr: 0,
+        propagation: 0,
+        userns_fd: 0,
+    };
+    let flags = libc::AT_EMPTY_PATH | if recursive { libc::AT_RECURSIVE } else { 0 };
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_mount_setattr,
+            mount.as_raw_fd(),
+            c"".as_ptr(),
+            flags,
+            &attr,
+            size_of::<MountAttr>(),
+        )
+    };
+    match res {
+        -1 => Err(std::io::Error::last_os_error().into()),
+        _ => Ok(()),
+    }
+}
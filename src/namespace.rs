@@ -0,0 +1,57 @@
+use std::fmt::Display;
+use std::io;
+use std::os::fd::{AsRawFd as _, FromRawFd as _, OwnedFd};
+
+use crate::WireError;
+
+/// The user and group ids of a process, as seen from its parent user namespace.
+#[derive(Clone, Copy)]
+pub(crate) struct Ids {
+    pub(crate) uid: u32,
+    pub(crate) gid: u32,
+}
+
+impl Ids {
+    /// Get the effective ids of the calling process, the only ones
+    /// an unprivileged process can map in a new user namespace.
+    pub(crate) fn current() -> Self {
+        let uid = unsafe { libc::geteuid() };
+        let gid = unsafe { libc::getegid() };
+        Self { uid, gid }
+    }
+
+    /// Map the ids to themselves in the new user namespace of the calling process.
+    ///
+    /// An unprivileged process can only map its own ids, and it needs
+    /// to give up `setgroups` before it can map its group.
+    pub(crate) fn map(self) -> Result<(), WireError> {
+        write("/proc/self/uid_map", format_args!("{0} {0} 1", self.uid))?;
+        write("/proc/self/setgroups", "deny")?;
+        write("/proc/self/gid_map", format_args!("{0} {0} 1", self.gid))?;
+        Ok(())
+    }
+}
+
+fn write(path: &str, content: impl Display) -> Result<(), WireError> {
+    std::fs::write(path, content.to_string())
+        .map_err(|err: io::Error| WireError::from(err).context(format_args!("write {path}")))
+}
+
+/// Bring up the loopback interface in the network namespace of the calling process.
+pub(crate) fn loopback_up() -> Result<(), WireError> {
+    let fail = |err| WireError::from(err).context("bring up the loopback interface");
+    let socket = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if socket == -1 {
+        return Err(fail(io::Error::last_os_error()));
+    }
+    let socket = unsafe { OwnedFd::from_raw_fd(socket) };
+    let mut req: libc::ifreq = unsafe { std::mem::zeroed() };
+    for (dst, src) in req.ifr_name.iter_mut().zip(c"lo".to_bytes()) {
+        *dst = *src as libc::c_char;
+    }
+    req.ifr_ifru.ifru_flags = (libc::IFF_UP | libc::IFF_RUNNING) as libc::c_short;
+    if unsafe { libc::ioctl(socket.as_raw_fd(), libc::SIOCSIFFLAGS, &req) } == -1 {
+        return Err(fail(io::Error::last_os_error()));
+    }
+    Ok(())
+}
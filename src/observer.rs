@@ -0,0 +1,180 @@
+use std::panic::Location;
+use std::process::ExitStatus;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::{Error, WireError, Zygote};
+
+/// Hooks called by the library on the events in the life of zygotes
+/// and their tasks, e.g., to collect metrics.
+///
+/// All the methods do nothing by default. They are called from the thread
+/// that caused the event, so they should return quickly, and shouldn't
+/// run tasks in the zygote they get.
+/// See [`set_observer()`] to install an observer.
+///
+/// Every task starts with a call to [`Observer::task_started()`], followed
+/// by a call to exactly one of [`Observer::task_finished()`],
+/// [`Observer::task_panicked()`], [`Observer::zygote_died()`] or
+/// [`Observer::channel_error()`].
+///
+/// ```rust
+/// # use std::panic::Location;
+/// # use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+/// # use zygote::{Observer, Zygote};
+/// static TASKS: AtomicUsize = AtomicUsize::new(0);
+///
+/// struct CountTasks;
+///
+/// impl Observer for CountTasks {
+///     fn task_started(&self, _: &Zygote, _: &'static Location<'static>) {
+///         TASKS.fetch_add(1, Relaxed);
+///     }
+/// }
+///
+/// assert!(zygote::set_observer(CountTasks).is_ok());
+/// Zygote::new().run(|_| {}, ());
+/// assert_eq!(TASKS.load(Relaxed), 1);
+/// ```
+pub trait Observer: Send + Sync + 'static {
+    /// A new zygote was created from the calling process,
+    /// see [`ZygoteBuilder::build()`](crate::ZygoteBuilder::build).
+    fn zygote_created(&self, zygote: &Zygote) {
+        let _ = zygote;
+    }
+
+    /// A new zygote was spawned from the `parent` zygote,
+    /// see [`Zygote::spawn()`] and [`Zygote::spawn_child()`].
+    fn zygote_spawned(&self, parent: &Zygote, zygote: &Zygote) {
+        let _ = (parent, zygote);
+    }
+
+    /// A task was sent to the zygote to run. The `task` is the location
+    /// in the source code that asked to run it.
+    fn task_started(&self, zygote: &Zygote, task: &'static Location<'static>) {
+        let _ = (zygote, task);
+    }
+
+    /// A task finished running in the zygote.
+    fn task_finished(
+        &self,
+        zygote: &Zygote,
+        task: &'static Location<'static>,
+        metrics: &TaskMetrics,
+    ) {
+        let _ = (zygote, task, metrics);
+    }
+
+    /// A task panicked in the zygote, or its arguments couldn't be decoded there.
+    fn task_panicked(&self, zygote: &Zygote, task: &'static Location<'static>, error: &WireError) {
+        let _ = (zygote, task, error);
+    }
+
+    /// The zygote died while running a task, with the exit `status`,
+    /// if it could be collected.
+    fn zygote_died(&self, zygote: &Zygote, status: Option<ExitStatus>) {
+        let _ = (zygote, status);
+    }
+
+    /// Communication with the zygote failed while running a task.
+    fn channel_error(&self, zygote: &Zygote, error: &Error) {
+        let _ = (zygote, error);
+    }
+}
+
+/// What it took to run a task, as seen from the process running it,
+/// see [`Observer::task_finished()`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TaskMetrics {
+    /// The time it took to send the task and get its result back.
+    pub duration: Duration,
+    /// The bytes sent to the zygote.
+    pub bytes_sent: u64,
+    /// The bytes received from the zygote.
+    pub bytes_received: u64,
+    /// The file descriptors sent to the zygote.
+    pub fds_sent: u64,
+    /// The file descriptors received from the zygote.
+    pub fds_received: u64,
+}
+
+static OBSERVER: OnceLock<Box<dyn Observer>> = OnceLock::new();
+
+/// Install the `observer` for the calling process. The observer can only be
+/// installed once, and this fails returning the `observer` if there's one already.
+///
+/// Zygotes created after installing the observer inherit it, and call it
+/// for the zygotes and tasks they start themselves.
+pub fn set_observer<O: Observer>(observer: O) -> Result<(), O> {
+    let mut observer = Some(observer);
+    OBSERVER.get_or_init(|| Box::new(observer.take().unwrap()));
+    match observer {
+        Some(observer) => Err(observer),
+        None => Ok(()),
+    }
+}
+
+/// Call the installed observer, if any.
+pub(crate) fn notify(f: impl FnOnce(&dyn Observer)) {
+    if let Some(observer) = OBSERVER.get() {
+        f(observer.as_ref());
+    }
+}
+
+/// An [`Observer`] that reports the events through the [metrics] facade.
+///
+/// The task metrics are labelled with the location of the task:
+/// * `zygote_created_total` and `zygote_spawned_total` count the created zygotes.
+/// * `zygote_tasks_started_total`, `zygote_tasks_finished_total` and
+///   `zygote_tasks_panicked_total` count the tasks.
+/// * `zygote_task_duration_seconds` is a histogram of the duration of the tasks.
+/// * `zygote_task_bytes_sent_total`, `zygote_task_bytes_received_total`,
+///   `zygote_task_fds_sent_total` and `zygote_task_fds_received_total`
+///   count the data sent to and received from the tasks.
+/// * `zygote_died_total` and `zygote_channel_errors_total` count the failures.
+///
+/// ```rust
+/// zygote::set_observer(zygote::MetricsObserver).unwrap();
+/// ```
+#[cfg(feature = "metrics")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MetricsObserver;
+
+#[cfg(feature = "metrics")]
+impl Observer for MetricsObserver {
+    fn zygote_created(&self, _: &Zygote) {
+        metrics::counter!("zygote_created_total").increment(1);
+    }
+
+    fn zygote_spawned(&self, _: &Zygote, _: &Zygote) {
+        metrics::counter!("zygote_spawned_total").increment(1);
+    }
+
+    fn task_started(&self, _: &Zygote, task: &'static Location<'static>) {
+        metrics::counter!("zygote_tasks_started_total", "task" => task.to_string()).increment(1);
+    }
+
+    fn task_finished(&self, _: &Zygote, task: &'static Location<'static>, metrics: &TaskMetrics) {
+        let labels = [("task", task.to_string())];
+        metrics::counter!("zygote_tasks_finished_total", &labels).increment(1);
+        metrics::histogram!("zygote_task_duration_seconds", &labels).record(metrics.duration);
+        metrics::counter!("zygote_task_bytes_sent_total", &labels).increment(metrics.bytes_sent);
+        metrics::counter!("zygote_task_bytes_received_total", &labels)
+            .increment(metrics.bytes_received);
+        metrics::counter!("zygote_task_fds_sent_total", &labels).increment(metrics.fds_sent);
+        metrics::counter!("zygote_task_fds_received_total", &labels)
+            .increment(metrics.fds_received);
+    }
+
+    fn task_panicked(&self, _: &Zygote, task: &'static Location<'static>, _: &WireError) {
+        metrics::counter!("zygote_tasks_panicked_total", "task" => task.to_string()).increment(1);
+    }
+
+    fn zygote_died(&self, _: &Zygote, _: Option<ExitStatus>) {
+        metrics::counter!("zygote_died_total").increment(1);
+    }
+
+    fn channel_error(&self, _: &Zygote, _: &Error) {
+        metrics::counter!("zygote_channel_errors_total").increment(1);
+    }
+}
@@ -98,16 +98,19 @@ impl Pipe {
         let n = swap_fds(vec![]).len();
         assert_eq!(n, 0, "orphaned file descriptors in channel");
 
-        let bytes = data.serialize()?;
+        let bytes = data.serialize();
+        let bytes = bytes.inspect_err(|_| crate::commit_transfers(false))?;
 
         // safety: BorrowedFd is repr(transparent) over RawFd
         let fds: Vec<BorrowedFd<'_>> = unsafe { transmute(swap_fds(vec![])) };
 
-        self.write_type_id::<T>()?;
-        self.write_sized(&bytes)?;
-        self.write_fds(&fds)?;
-
-        Ok(())
+        let res = self
+            .write_type_id::<T>()
+            .and_then(|_| self.write_sized(&bytes))
+            .and_then(|_| self.write_fds(&fds));
+        // the zygotes sent along with the data now belong to the receiving end
+        crate::commit_transfers(res.is_ok());
+        Ok(res?)
     }
 
     pub fn recv_parts_into(
@@ -141,6 +144,32 @@ impl Pipe {
     pub fn recv<T: Wire>(&mut self) -> Result<T, Error> {
         self.recv_delayed()?.deserialize::<T>()
     }
+
+    /// The data sent and received through this end of the pipe.
+    pub fn traffic(&self) -> Traffic {
+        self.0.traffic()
+    }
+}
+
+/// The data sent and received through a pipe.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Traffic {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub fds_sent: u64,
+    pub fds_received: u64,
+}
+
+impl Traffic {
+    /// The data sent and received after `earlier`.
+    pub fn since(&self, earlier: &Traffic) -> Traffic {
+        Traffic {
+            bytes_sent: self.bytes_sent - earlier.bytes_sent,
+            bytes_received: self.bytes_received - earlier.bytes_received,
+            fds_sent: self.fds_sent - earlier.fds_sent,
+            fds_received: self.fds_received - earlier.fds_received,
+        }
+    }
 }
 
 pub struct DelayedRecv {
@@ -150,6 +179,11 @@ pub struct DelayedRecv {
 }
 
 impl DelayedRecv {
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    pub fn is<T: Wire>(&self) -> bool {
+        self.type_id == TypeId::of::<T>()
+    }
+
     pub fn deserialize<T: Wire>(self) -> Result<T, Error> {
         if self.type_id != TypeId::of::<T>() {
             return Err(Error::Decode(rmp_serde::decode::Error::Uncategorized(
@@ -5,6 +5,8 @@ use std::os::unix::net::UnixStream as StdUnixStream;
 
 use nix::sys::socket::{recvmsg, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags};
 
+use super::Traffic;
+
 // According to https://man7.org/linux/man-pages/man7/unix.7.html
 // we can send up to 253 FDs per message, however until recently
 // in musl targets the buffer was limit to 1024 bytes, which holds
@@ -17,6 +19,7 @@ pub struct UnixStream {
     inner: StdUnixStream,
     fds: Vec<OwnedFd>,
     cmsg: Vec<u8>,
+    traffic: Traffic,
 }
 
 impl UnixStream {
@@ -25,6 +28,7 @@ impl UnixStream {
             inner,
             fds: vec![],
             cmsg: nix::cmsg_space!([RawFd; SCM_MAX_FD]),
+            traffic: Traffic::default(),
         }
     }
 
@@ -49,9 +53,11 @@ impl UnixStream {
             if let ControlMessageOwned::ScmRights(fds) = cmsg {
                 // Safety: OwnedFd is repr(transparent) over RawFd
                 let fds: Vec<OwnedFd> = unsafe { transmute(fds) };
+                self.traffic.fds_received += fds.len() as u64;
                 self.fds.extend(fds);
             }
         }
+        self.traffic.bytes_received += recvmsg.bytes as u64;
         Ok(recvmsg.bytes)
     }
 
@@ -67,12 +73,18 @@ impl UnixStream {
             MsgFlags::empty(),
             None,
         )?;
+        self.traffic.bytes_sent += sendmsg as u64;
+        self.traffic.fds_sent += fds.len() as u64;
         Ok(sendmsg)
     }
 
     pub fn take_fds(&mut self) -> Vec<OwnedFd> {
         std::mem::take(&mut self.fds)
     }
+
+    pub fn traffic(&self) -> Traffic {
+        self.traffic
+    }
 }
 
 impl io::Read for UnixStream {
@@ -83,11 +95,15 @@ impl io::Read for UnixStream {
 
 impl io::Write for UnixStream {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.inner.write(buf)
+        let n = self.inner.write(buf)?;
+        self.traffic.bytes_sent += n as u64;
+        Ok(n)
     }
 
     fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
-        self.inner.write_vectored(bufs)
+        let n = self.inner.write_vectored(bufs)?;
+        self.traffic.bytes_sent += n as u64;
+        Ok(n)
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -0,0 +1,101 @@
+use std::fmt::Display;
+use std::io;
+
+use caps::{CapSet, Capability, CapsHashSet};
+use serde::{Deserialize, Serialize};
+
+use crate::WireError;
+
+/// The identity and privileges a zygote runs with.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub(crate) struct Privileges {
+    pub(crate) uid: Option<u32>,
+    pub(crate) gid: Option<u32>,
+    pub(crate) groups: Option<Vec<u32>>,
+    pub(crate) capabilities: Option<Vec<Capability>>,
+    pub(crate) ambient_capabilities: Vec<Capability>,
+    pub(crate) securebits: Option<u32>,
+    pub(crate) no_new_privs: bool,
+}
+
+fn check(res: libc::c_int, what: impl Display) -> Result<(), WireError> {
+    match res {
+        -1 => Err(WireError::from(io::Error::last_os_error()).context(what)),
+        _ => Ok(()),
+    }
+}
+
+impl Privileges {
+    /// Apply the privileges to the calling process.
+    pub(crate) fn apply(&self) -> Result<(), WireError> {
+        let capabilities: Option<CapsHashSet> = self
+            .capabilities
+            .as_ref()
+            .map(|caps| caps.iter().copied().collect());
+        let switch_user = self.uid.is_some() || self.gid.is_some();
+
+        // Changing the bounding set and the securebits requires CAP_SETPCAP,
+        // which we could lose once we switch to another user.
+        if let Some(caps) = &capabilities {
+            for cap in caps::all().difference(caps) {
+                if caps::has_cap(None, CapSet::Bounding, *cap).unwrap_or(false) {
+                    caps::drop(None, CapSet::Bounding, *cap)
+                        .map_err(|err| WireError::from(err).context("drop capabilities"))?;
+                }
+            }
+        }
+        // keep the permitted capabilities when switching to another user
+        let keep_caps = capabilities.is_some() && switch_user;
+        if let Some(bits) = self.securebits {
+            let bits = bits | if keep_caps { SECBIT_KEEP_CAPS } else { 0 };
+            check(
+                unsafe { libc::prctl(libc::PR_SET_SECUREBITS, bits as libc::c_ulong) },
+                "set securebits",
+            )?;
+        } else if keep_caps {
+            check(
+                unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 1) },
+                "keep capabilities",
+            )?;
+        }
+
+        // don't keep the supplementary groups of the previous user
+        let groups = match &self.groups {
+            Some(groups) => Some(groups.as_slice()),
+            None => switch_user.then_some(&[][..]),
+        };
+        if let Some(groups) = groups {
+            let res = unsafe { libc::setgroups(groups.len() as _, groups.as_ptr()) };
+            check(res, "set supplementary groups")?;
+        }
+        if let Some(gid) = self.gid {
+            check(unsafe { libc::setresgid(gid, gid, gid) }, "set gid")?;
+        }
+        if let Some(uid) = self.uid {
+            check(unsafe { libc::setresuid(uid, uid, uid) }, "set uid")?;
+        }
+
+        if let Some(caps) = &capabilities {
+            // the effective set must always be a subset of the permitted set
+            for set in [CapSet::Effective, CapSet::Inheritable, CapSet::Permitted] {
+                caps::set(None, set, caps)
+                    .map_err(|err| WireError::from(err).context("set capabilities"))?;
+            }
+        }
+        for cap in &self.ambient_capabilities {
+            caps::raise(None, CapSet::Ambient, *cap).map_err(|err| {
+                WireError::from(err).context(format_args!("raise ambient capability {cap}"))
+            })?;
+        }
+
+        if self.no_new_privs {
+            check(
+                unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) },
+                "set no_new_privs",
+            )?;
+        }
+        Ok(())
+    }
+}
+
+const SECBIT_KEEP_CAPS: u32 = 1 << 4;
@@ -0,0 +1,397 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::os::fd::{AsFd as _, AsRawFd as _, BorrowedFd, FromRawFd as _, OwnedFd, RawFd};
+use std::os::unix::process::ExitStatusExt as _;
+use std::process::ExitStatus;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+
+use libc::{PR_SET_PDEATHSIG, SIGKILL};
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::sys::wait::{waitid, Id, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+
+use crate::WireFd;
+
+/// A process we keep track of.
+///
+/// Processes are referred by a pidfd, so that we can poll for their exit,
+/// and so that they can't be confused with another process that reuses
+/// their pid. On kernels without pidfd support (before 5.3), they are
+/// referred by their pid instead, and we need to actively check for their exit.
+#[derive(Serialize, Deserialize)]
+pub(crate) enum Process {
+    Pidfd(WireFd<OwnedFd>),
+    Pid(libc::pid_t),
+}
+
+impl Process {
+    /// Refer to the process `pid`.
+    pub(crate) fn open(pid: libc::pid_t) -> io::Result<Process> {
+        match unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) } {
+            -1 if Errno::last() == Errno::ENOSYS => Ok(Process::Pid(pid)),
+            -1 => Err(io::Error::last_os_error()),
+            fd => Ok(Process::Pidfd(unsafe { WireFd::from_raw_fd(fd as _) })),
+        }
+    }
+
+    /// Refer to the process `pid` through its `pidfd`, or through
+    /// the pid itself if we don't have a pidfd.
+    pub(crate) fn from_raw(pidfd: RawFd, pid: libc::pid_t) -> Process {
+        match pidfd {
+            -1 => Process::Pid(pid),
+            // pidfds from `CLONE_PIDFD` predate the ability to poll them (5.2 vs 5.3)
+            fd if !*PIDFD_POLL => {
+                drop(unsafe { OwnedFd::from_raw_fd(fd) });
+                Process::Pid(pid)
+            }
+            fd => Process::Pidfd(unsafe { WireFd::from_raw_fd(fd) }),
+        }
+    }
+
+    pub(crate) fn try_clone(&self) -> io::Result<Process> {
+        match self {
+            Process::Pidfd(pidfd) => Ok(Process::Pidfd(WireFd::new(pidfd.try_clone()?))),
+            Process::Pid(pid) => Ok(Process::Pid(*pid)),
+        }
+    }
+
+    /// Get the pidfd of the process, if we have one, e.g., to poll for its exit.
+    pub(crate) fn pidfd(&self) -> Option<BorrowedFd<'_>> {
+        match self {
+            Process::Pidfd(pidfd) => Some(pidfd.as_fd()),
+            Process::Pid(_) => None,
+        }
+    }
+
+    /// Get the pid of the process, as seen from the pid namespace
+    /// of the calling process.
+    pub(crate) fn pid(&self) -> io::Result<libc::pid_t> {
+        let pidfd = match self {
+            Process::Pidfd(pidfd) => pidfd.as_raw_fd(),
+            Process::Pid(pid) => return Ok(*pid),
+        };
+        let fdinfo = fs::read_to_string(format!("/proc/self/fdinfo/{pidfd}"))?;
+        fdinfo
+            .lines()
+            .find_map(|line| line.strip_prefix("Pid:"))
+            .and_then(|pid| pid.trim().parse().ok())
+            .filter(|pid| *pid > 0)
+            .ok_or_else(|| io::Error::from(ErrorKind::NotFound))
+    }
+
+    fn id(&self) -> io::Result<Id<'_>> {
+        match self {
+            Process::Pidfd(pidfd) if *PIDFD_WAITID => Ok(Id::PIDFd(pidfd.as_fd())),
+            _ => Ok(Id::Pid(Pid::from_raw(self.pid()?))),
+        }
+    }
+
+    /// Send a signal to the process.
+    pub(crate) fn send_signal(&self, signal: i32) -> io::Result<()> {
+        let res = match self {
+            Process::Pidfd(pidfd) => unsafe {
+                libc::syscall(libc::SYS_pidfd_send_signal, pidfd.as_raw_fd(), signal, 0, 0)
+            },
+            Process::Pid(pid) => unsafe { libc::kill(*pid, signal) as _ },
+        };
+        match res {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Wait for the process to exit.
+    /// Returns `false` if the process is still running after `timeout`.
+    pub(crate) fn wait_exit(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let remaining = || deadline.map(|d| d.saturating_duration_since(Instant::now()));
+        let Process::Pidfd(pidfd) = self else {
+            // without a pidfd, all we can do is to check every now and then
+            loop {
+                if self.has_exited()? {
+                    return Ok(true);
+                }
+                match remaining() {
+                    Some(Duration::ZERO) => return Ok(false),
+                    remaining => {
+                        std::thread::sleep(remaining.unwrap_or(POLL_INTERVAL).min(POLL_INTERVAL))
+                    }
+                }
+            }
+        };
+        // A pidfd becomes readable once the process exits, this works even
+        // for processes that are not children of the calling process.
+        let mut fds = [PollFd::new(pidfd.as_fd(), PollFlags::POLLIN)];
+        loop {
+            let timeout = match remaining() {
+                None => PollTimeout::NONE,
+                Some(remaining) => PollTimeout::try_from(remaining).unwrap_or(PollTimeout::MAX),
+            };
+            match poll(&mut fds, timeout) {
+                Ok(0) => return Ok(false),
+                Ok(_) => return Ok(true),
+                Err(Errno::EINTR) => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Check if a process referred by its pid has exited.
+    fn has_exited(&self) -> io::Result<bool> {
+        if self.exit_status()?.is_some() {
+            return Ok(true);
+        }
+        // not our child, it's gone once it has been reaped
+        let pid = self.pid()?;
+        let res = unsafe { libc::kill(pid, 0) };
+        Ok(res == -1 && Errno::last() == Errno::ESRCH)
+    }
+
+    /// Reap the process, waiting for it to exit.
+    /// Returns `None` if the process is not a child of the calling process,
+    /// as we can't obtain its exit status.
+    pub(crate) fn reap(&self) -> io::Result<Option<ExitStatus>> {
+        waitid_status(self, WaitPidFlag::WEXITED)
+    }
+
+    /// Reap the process if it has already exited.
+    pub(crate) fn try_reap(&self) -> io::Result<Option<ExitStatus>> {
+        waitid_status(self, WaitPidFlag::WEXITED | WaitPidFlag::WNOHANG)
+    }
+
+    /// Get the exit status of the process if it has already exited,
+    /// without reaping it.
+    pub(crate) fn exit_status(&self) -> io::Result<Option<ExitStatus>> {
+        let flags = WaitPidFlag::WEXITED | WaitPidFlag::WNOWAIT | WaitPidFlag::WNOHANG;
+        waitid_status(self, flags)
+    }
+
+    /// Wait for the process to exit, and reap it if it is a child
+    /// of the calling process.
+    pub(crate) fn wait(&self) -> io::Result<Option<ExitStatus>> {
+        self.wait_exit(None)?;
+        self.reap()
+    }
+}
+
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Whether pidfds can be polled for the exit of their process, since 5.3,
+/// the same version `pidfd_open` was introduced in.
+static PIDFD_POLL: LazyLock<bool> =
+    LazyLock::new(
+        || match unsafe { libc::syscall(libc::SYS_pidfd_open, libc::getpid(), 0) } {
+            -1 => Errno::last() != Errno::ENOSYS,
+            fd => {
+                drop(unsafe { OwnedFd::from_raw_fd(fd as RawFd) });
+                true
+            }
+        },
+    );
+
+/// Whether `waitid` accepts pidfds (`P_PIDFD`), since 5.4.
+/// Older kernels reject the id type with `EINVAL`, newer ones the bad fd with `EBADF`.
+static PIDFD_WAITID: LazyLock<bool> = LazyLock::new(|| {
+    let flags = libc::WEXITED | libc::WNOHANG;
+    let res = unsafe { libc::waitid(libc::P_PIDFD, RawFd::MAX as _, std::ptr::null_mut(), flags) };
+    res == 0 || Errno::last() != Errno::EINVAL
+});
+
+fn waitid_status(process: &Process, flags: WaitPidFlag) -> io::Result<Option<ExitStatus>> {
+    // siblings are created without an exit signal, and would be skipped otherwise
+    let flags = flags | WaitPidFlag::__WALL;
+    loop {
+        let status = match waitid(process.id()?, flags) {
+            Ok(WaitStatus::Exited(_, code)) => ExitStatus::from_raw(code << 8),
+            Ok(WaitStatus::Signaled(_, signal, core)) => {
+                ExitStatus::from_raw(signal as i32 | if core { 0x80 } else { 0 })
+            }
+            Ok(WaitStatus::StillAlive) => return Ok(None),
+            Ok(_) | Err(Errno::EINTR) => continue,
+            Err(Errno::ECHILD) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        return Ok(Some(status));
+    }
+}
+
+/// Get the signal the calling process receives when its parent dies, if any.
+pub(crate) fn parent_death_signal() -> Option<i32> {
+    let mut signal: libc::c_int = 0;
+    unsafe { libc::prctl(libc::PR_GET_PDEATHSIG, &mut signal) };
+    (signal != 0).then_some(signal)
+}
+
+/// Make the calling process receive `signal` when its `parent` dies.
+pub(crate) fn die_with_parent(parent: &Process, signal: i32) {
+    unsafe { libc::prctl(PR_SET_PDEATHSIG, signal) };
+    // The parent could have died before we set the signal.
+    let alive = match parent {
+        // We can't compare against getppid(), as the parent is not
+        // visible from within a new pid namespace.
+        Process::Pidfd(_) => matches!(parent.wait_exit(Some(Duration::ZERO)), Ok(false)),
+        // The pid of the parent is not valid from within a new pid namespace
+        // either, and there getppid() is always 0, so we can't tell.
+        Process::Pid(pid) => {
+            let ppid = unsafe { libc::getppid() };
+            ppid == 0 || ppid == *pid
+        }
+    };
+    if !alive {
+        std::process::exit(0);
+    }
+}
+
+/// Kill all the descendants of the process `pid` with `SIGKILL`.
+///
+/// Descendants that can't be killed, e.g., because they run as another user,
+/// are left alive. This gives up after [`KILL_TIMEOUT`], if the tree keeps growing.
+pub(crate) fn kill_descendants(pid: libc::pid_t) {
+    let deadline = Instant::now() + KILL_TIMEOUT;
+    // Processes can keep forking while we walk the tree, keep going until
+    // every process left has been signalled, or can't be. Killed processes
+    // might take a while to exit, e.g., if they're blocked in the kernel,
+    // but they won't fork anymore.
+    let mut signalled = HashSet::new();
+    while Instant::now() < deadline {
+        let mut done = true;
+        for pid in descendants(pid) {
+            if !signalled.insert(pid) {
+                continue;
+            }
+            // processes we're not allowed to kill (EPERM) are left alone,
+            // and those that are already gone (ESRCH) won't fork anymore
+            if unsafe { libc::kill(pid, SIGKILL) } == 0 {
+                done = false;
+            }
+        }
+        if done {
+            return;
+        }
+    }
+}
+
+const KILL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// List the descendants of the process `pid` that are still alive.
+fn descendants(pid: libc::pid_t) -> Vec<libc::pid_t> {
+    let mut children = HashMap::<libc::pid_t, Vec<libc::pid_t>>::new();
+    let entries = fs::read_dir("/proc").into_iter().flatten().flatten();
+    for entry in entries {
+        let Some(child) = entry.file_name().to_str().and_then(|n| n.parse().ok()) else {
+            continue;
+        };
+        let Ok(stat) = fs::read_to_string(entry.path().join("stat")) else {
+            continue;
+        };
+        // the fields after the command name are "state ppid ..."
+        let mut fields = stat
+            .rsplit_once(')')
+            .unwrap_or_default()
+            .1
+            .split_whitespace();
+        let (Some(state), Some(Ok(parent))) = (fields.next(), fields.next().map(str::parse)) else {
+            continue;
+        };
+        if state != "Z" && state != "X" {
+            children.entry(parent).or_default().push(child);
+        }
+    }
+
+    let mut descendants = vec![];
+    let mut pending = vec![pid];
+    while let Some(pid) = pending.pop() {
+        let children = children.remove(&pid).unwrap_or_default();
+        descendants.extend_from_slice(&children);
+        pending.extend(children);
+    }
+    descendants
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::process::ExitStatusExt as _;
+    use std::process::Command;
+    use std::time::Duration;
+
+    use super::{die_with_parent, kill_descendants, Process};
+    use crate::clone::clone3_or_clone;
+
+    #[test]
+    fn pid_fallback() {
+        let pid = Command::new("sleep").arg("1000").spawn().unwrap().id();
+        let process = Process::Pid(pid as libc::pid_t);
+
+        assert!(!process.wait_exit(Some(Duration::from_millis(50))).unwrap());
+        assert!(process.exit_status().unwrap().is_none());
+
+        process.send_signal(libc::SIGKILL).unwrap();
+        let status = process.wait().unwrap().unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGKILL));
+    }
+
+    #[test]
+    fn die_with_pid_parent() {
+        let parent = Process::Pid(unsafe { libc::getpid() });
+        // the parent is alive, even if it's not visible from a new pid namespace
+        for flags in [0, libc::CLONE_NEWPID] {
+            // creating a pid namespace needs privileges
+            let Ok(child) = clone3_or_clone(flags, libc::SIGCHLD, None) else {
+                continue;
+            };
+            let Some(child) = child else {
+                die_with_parent(&parent, libc::SIGKILL);
+                unsafe { libc::_exit(3) };
+            };
+            assert_eq!(child.wait().unwrap().unwrap().code(), Some(3));
+        }
+    }
+
+    #[test]
+    fn kill_tree() {
+        let mut child = Command::new("sh")
+            .args(["-c", "sleep 1000 & sleep 1000"])
+            .spawn()
+            .unwrap();
+        let pid = child.id() as libc::pid_t;
+
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert!(!super::descendants(pid).is_empty());
+
+        kill_descendants(pid);
+        assert!(super::descendants(pid).is_empty());
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn kill_tree_without_permission() {
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+        let mut child = Command::new("sh")
+            .args(["-c", "sleep 1000 & sleep 1000"])
+            .spawn()
+            .unwrap();
+        let pid = child.id() as libc::pid_t;
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        // the descendants run as root, another user can't kill them
+        let zygote = crate::Zygote::builder()
+            .uid(65534)
+            .gid(65534)
+            .build()
+            .unwrap();
+        zygote.run(kill_descendants, pid);
+        assert_eq!(super::descendants(pid).len(), 2);
+
+        kill_descendants(pid);
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read as _};
+use std::mem::zeroed;
+use std::os::fd::{FromRawFd as _, IntoRawFd as _, OwnedFd};
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::atomic::{AtomicBool, AtomicI32};
+
+use nix::fcntl::OFlag;
+use nix::unistd::pipe2;
+use seccompiler::{apply_filter, BpfProgram, SeccompAction, SeccompFilter, TargetArch};
+use serde::{Deserialize, Serialize};
+
+use crate::WireError;
+
+/// A seccomp-bpf policy restricting the system calls a zygote can make.
+///
+/// Violations of the policy terminate the zygote, and the running task
+/// fails with [`Error::ForbiddenSyscall`](crate::Error::ForbiddenSyscall).
+/// The system calls the zygote needs to receive tasks and send back their
+/// results (e.g., `recvmsg` and `sendmsg`) are always allowed, and denying
+/// any of them fails to create the zygote.
+///
+/// Syscalls are identified by their number, e.g., [`libc::SYS_getpid`].
+///
+/// ```rust
+/// # use zygote::{Error, SeccompPolicy, Zygote};
+/// let zygote = Zygote::builder()
+///     .seccomp(SeccompPolicy::compute())
+///     .build()
+///     .unwrap();
+/// assert_eq!(zygote.run(|x: u32| x * 2, 21), 42);
+///
+/// let err = zygote.try_run(|_| std::fs::read("/etc/hostname").is_ok(), ());
+/// assert!(matches!(err, Err(Error::ForbiddenSyscall(_))));
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SeccompPolicy {
+    allow: bool,
+    syscalls: Vec<i64>,
+}
+
+impl SeccompPolicy {
+    /// Allow only the given syscalls, and forbid any other.
+    pub fn allow(syscalls: impl IntoIterator<Item = i64>) -> Self {
+        let syscalls = syscalls.into_iter().collect();
+        Self {
+            allow: true,
+            syscalls,
+        }
+    }
+
+    /// Forbid the given syscalls, and allow any other.
+    pub fn deny(syscalls: impl IntoIterator<Item = i64>) -> Self {
+        let syscalls = syscalls.into_iter().collect();
+        Self {
+            allow: false,
+            syscalls,
+        }
+    }
+
+    /// A policy for tasks that only do computations on their arguments.
+    /// It allows managing memory, threads synchronization, and reading
+    /// clocks, but it doesn't allow opening files, creating processes,
+    /// or sending signals.
+    pub fn compute() -> Self {
+        Self::allow(COMPUTE_SYSCALLS.iter().copied())
+    }
+
+    fn filter(&self) -> Result<BpfProgram, WireError> {
+        let (syscalls, mismatch, matched): (Vec<i64>, _, _) = match self.allow {
+            true => {
+                let syscalls = self.syscalls.iter().chain(ZYGOTE_SYSCALLS);
+                let syscalls = syscalls.copied().collect();
+                (syscalls, SeccompAction::Trap, SeccompAction::Allow)
+            }
+            false => {
+                if let Some(nr) = self.syscalls.iter().find(|nr| ZYGOTE_SYSCALLS.contains(nr)) {
+                    let name = syscall_name(*nr as u32);
+                    let msg = format!("{name} can't be denied, the zygote needs it");
+                    return Err(WireError::from_str(msg));
+                }
+                (
+                    self.syscalls.clone(),
+                    SeccompAction::Allow,
+                    SeccompAction::Trap,
+                )
+            }
+        };
+        let rules: BTreeMap<_, _> = syscalls.into_iter().map(|nr| (nr, vec![])).collect();
+        let arch = TargetArch::try_from(std::env::consts::ARCH)?;
+        let filter = SeccompFilter::new(rules, mismatch, matched, arch)?;
+        Ok(filter.try_into()?)
+    }
+
+    /// Install the policy in the calling thread.
+    pub(crate) fn install(&self) -> Result<(), WireError> {
+        let filter = self.filter()?;
+        let mut action: libc::sigaction = unsafe { zeroed() };
+        action.sa_sigaction = on_sigsys as *const () as usize;
+        action.sa_flags = libc::SA_SIGINFO;
+        if unsafe { libc::sigaction(libc::SIGSYS, &action, std::ptr::null_mut()) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        apply_filter(&filter)?;
+        INSTALLED.store(true, Relaxed);
+        Ok(())
+    }
+}
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+static REPORT_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// Whether a policy is installed in the calling process, or was inherited from its creator.
+pub(crate) fn installed() -> bool {
+    INSTALLED.load(Relaxed)
+}
+
+/// Create a pipe to report violations on. The caller keeps the read end,
+/// see [`reported()`], and the zygote the write end, see [`report_to()`].
+pub(crate) fn report_pipe() -> io::Result<(File, OwnedFd)> {
+    let (read, write) = pipe2(OFlag::O_CLOEXEC | OFlag::O_NONBLOCK)?;
+    Ok((read.into(), write))
+}
+
+/// Report the violations of the calling process on the pipe `fd`,
+/// instead of the one inherited from its creator, if any.
+pub(crate) fn report_to(fd: OwnedFd) {
+    let old = REPORT_FD.swap(fd.into_raw_fd(), Relaxed);
+    if old != -1 {
+        drop(unsafe { OwnedFd::from_raw_fd(old) });
+    }
+}
+
+/// Get the syscall the zygote reported on the `pipe` before it was terminated, if any.
+pub(crate) fn reported(mut pipe: &File) -> Option<u32> {
+    let mut nr = [0u8; size_of::<u32>()];
+    pipe.read_exact(&mut nr).ok()?;
+    Some(u32::from_ne_bytes(nr))
+}
+
+#[repr(C)]
+struct SigsysInfo {
+    signo: i32,
+    errno: i32,
+    code: i32,
+    call_addr: *mut libc::c_void,
+    syscall: i32,
+    arch: u32,
+}
+
+extern "C" fn on_sigsys(_: i32, info: *mut libc::siginfo_t, _: *mut libc::c_void) {
+    // We are in a signal handler, we can't allocate. Report the syscall
+    // number as is on the side pipe, and exit without unwinding.
+    let nr = unsafe { (*(info as *const SigsysInfo)).syscall } as u32;
+    let fd = REPORT_FD.load(Relaxed);
+    unsafe {
+        libc::write(fd, nr.to_ne_bytes().as_ptr() as *const _, size_of::<u32>());
+        libc::_exit(128 + libc::SIGSYS);
+    }
+}
+
+/// Get the name of the syscall `nr`.
+pub(crate) fn syscall_name(nr: u32) -> String {
+    match SYSCALL_NAMES.iter().find(|(n, _)| *n == nr as i64) {
+        Some((_, name)) => (*name).to_owned(),
+        None => format!("syscall {nr}"),
+    }
+}
+
+macro_rules! syscalls {
+    ($($(#[$attr:meta])* $name:ident),* $(,)?) => {
+        &[$($(#[$attr])* libc::$name),*]
+    };
+}
+
+/// Syscalls the zygote needs to receive tasks and send back their results.
+const ZYGOTE_SYSCALLS: &[i64] = syscalls![
+    SYS_read,
+    SYS_write,
+    SYS_recvfrom,
+    SYS_sendto,
+    SYS_recvmsg,
+    SYS_sendmsg,
+    SYS_ppoll,
+    #[cfg(target_arch = "x86_64")]
+    SYS_poll,
+    SYS_ioctl,
+    SYS_close,
+    SYS_waitid,
+    SYS_brk,
+    SYS_mmap,
+    SYS_munmap,
+    SYS_mremap,
+    SYS_mprotect,
+    SYS_madvise,
+    SYS_futex,
+    SYS_rt_sigreturn,
+    SYS_rt_sigprocmask,
+    SYS_sigaltstack,
+    SYS_restart_syscall,
+    SYS_exit,
+    SYS_exit_group,
+];
+
+/// Syscalls allowed by [`SeccompPolicy::compute()`], on top of `ZYGOTE_SYSCALLS`.
+const COMPUTE_SYSCALLS: &[i64] = syscalls![
+    SYS_clock_gettime,
+    SYS_clock_getres,
+    SYS_clock_nanosleep,
+    SYS_gettimeofday,
+    SYS_nanosleep,
+    SYS_getrandom,
+    SYS_sched_yield,
+    SYS_sched_getaffinity,
+    SYS_getpid,
+    SYS_gettid,
+];
+
+/// Names of the syscalls of the target, generated from the kernel headers by the build script.
+const SYSCALL_NAMES: &[(i64, &str)] = include!(concat!(env!("OUT_DIR"), "/syscall_names.rs"));
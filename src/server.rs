@@ -0,0 +1,214 @@
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, RawFd};
+use std::os::linux::net::SocketAddrExt as _;
+use std::os::unix::fs::{MetadataExt as _, OpenOptionsExt as _};
+use std::os::unix::net::{SocketAddr, UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+use nix::sys::socket::{getsockopt, sockopt};
+use serde::{Deserialize, Serialize};
+
+use crate::pipe::Pipe;
+use crate::process::Process;
+use crate::{WireError, WireFd};
+
+thread_local! {
+    pub(crate) static LISTENERS: RefCell<Vec<(UnixListener, Allowlist)>> = const { RefCell::new(vec![]) };
+}
+
+/// Address of a unix domain socket a zygote can listen on.
+///
+/// ```rust
+/// # use zygote::Address;
+/// let addr = Address::from("/run/my-zygote.sock"); // a filesystem socket
+/// let addr = Address::abstract_name("my-zygote"); // an abstract socket
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Address {
+    /// A socket bound to a path in the filesystem
+    Path(PathBuf),
+    /// A socket in the abstract namespace
+    Abstract(Vec<u8>),
+}
+
+impl Address {
+    /// Create an address in the abstract socket namespace.
+    pub fn abstract_name(name: impl AsRef<[u8]>) -> Self {
+        Self::Abstract(name.as_ref().to_vec())
+    }
+
+    pub(crate) fn to_socket_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Self::Path(path) => SocketAddr::from_pathname(path),
+            Self::Abstract(name) => SocketAddr::from_abstract_name(name),
+        }
+    }
+}
+
+impl From<PathBuf> for Address {
+    fn from(path: PathBuf) -> Self {
+        Self::Path(path)
+    }
+}
+
+impl From<&Path> for Address {
+    fn from(path: &Path) -> Self {
+        Self::Path(path.to_owned())
+    }
+}
+
+impl From<&str> for Address {
+    fn from(path: &str) -> Self {
+        Self::Path(path.into())
+    }
+}
+
+impl From<String> for Address {
+    fn from(path: String) -> Self {
+        Self::Path(path.into())
+    }
+}
+
+/// Set of peers allowed to connect to a listening zygote.
+///
+/// Peers are identified by the credentials of the connecting process
+/// (see `SO_PEERCRED` in [unix(7)](https://man7.org/linux/man-pages/man7/unix.7.html)).
+/// A peer is accepted if its user or group is in the allowlist.
+///
+/// Regardless of the allowlist, peers must be running the same executable
+/// as the zygote, as tasks are sent as plain function pointers. Peers show
+/// which executable they run by sending a handle to it when connecting, so
+/// the zygote doesn't need any access to the peer process, e.g., when it runs
+/// as another user. This protects against mismatched executables, not against
+/// malicious peers: only the allowlist decides who can run tasks in the zygote.
+///
+/// ```rust
+/// # use zygote::Allowlist;
+/// let allowlist = Allowlist::current_user().allow_gid(1000);
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Allowlist {
+    uids: Vec<u32>,
+    gids: Vec<u32>,
+}
+
+impl Allowlist {
+    /// Create an empty allowlist that rejects every peer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an allowlist that accepts peers running as the effective
+    /// user of the calling process.
+    pub fn current_user() -> Self {
+        Self::new().allow_uid(unsafe { libc::geteuid() })
+    }
+
+    /// Accept peers running as the user `uid`.
+    pub fn allow_uid(mut self, uid: u32) -> Self {
+        self.uids.push(uid);
+        self
+    }
+
+    /// Accept peers running as the group `gid`.
+    pub fn allow_gid(mut self, gid: u32) -> Self {
+        self.gids.push(gid);
+        self
+    }
+
+    fn check(&self, peer: &impl AsFd) -> Result<(), WireError> {
+        let cred = getsockopt(peer, sockopt::PeerCredentials)?;
+        if !self.uids.contains(&cred.uid()) && !self.gids.contains(&cred.gid()) {
+            return Err(WireError::from_str(format!(
+                "peer with uid {} and gid {} is not allowed",
+                cred.uid(),
+                cred.gid()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Open the executable of the current process, without requiring read access to it.
+fn open_executable() -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_PATH)
+        .open("/proc/self/exe")
+}
+
+/// Check that the executable sent by the peer is the one we're running.
+fn check_executable(pipe: &mut Pipe) -> Result<(), WireError> {
+    let peer_exe = pipe.recv::<WireFd<File>>()?.into_inner().metadata()?;
+    let exe = open_executable()?.metadata()?;
+    if (exe.dev(), exe.ino()) != (peer_exe.dev(), peer_exe.ino()) {
+        return Err(WireError::from_str(
+            "peer is running a different executable",
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn start_listening((listener, allowlist): (WireFd<UnixListener>, Allowlist)) {
+    let listener = listener.into_inner();
+    LISTENERS.with_borrow_mut(|listeners| listeners.push((listener, allowlist)));
+}
+
+pub(crate) fn listener_fds() -> Vec<RawFd> {
+    LISTENERS.with_borrow(|listeners| {
+        listeners
+            .iter()
+            .map(|(listener, _)| listener.as_fd().as_raw_fd())
+            .collect()
+    })
+}
+
+/// Accept a pending connection on the n-th listener, returning the connection
+/// if the peer is allowed in.
+pub(crate) fn accept(n: usize) -> Option<Pipe> {
+    let (stream, allowlist) = LISTENERS.with_borrow(|listeners| {
+        let (listener, allowlist) = &listeners[n];
+        let (stream, _) = listener.accept().ok()?;
+        Some((stream, allowlist.clone()))
+    })?;
+    let mut pipe = Pipe::new(stream.into());
+    match allowlist.check(&pipe) {
+        Ok(()) => Some(pipe),
+        Err(err) => {
+            let _ = pipe.send::<Handshake>(Err(err));
+            None
+        }
+    }
+}
+
+/// What the zygote sends to the peers it accepts: a handle to the zygote
+/// process, and the pipe it reports seccomp violations on, if any.
+type Handshake = Result<(Process, Option<WireFd<File>>), WireError>;
+
+/// Check the executable of the peer on the other end of the connection,
+/// and send it a handle to the current process if it's the expected one.
+pub(crate) fn handshake(pipe: &mut Pipe, violations: Option<File>) -> Result<(), crate::Error> {
+    if let Err(err) = check_executable(pipe) {
+        pipe.send::<Handshake>(Err(err.clone()))?;
+        return Err(err.into());
+    }
+    let process = Process::open(unsafe { libc::getpid() }).map_err(WireError::from);
+    pipe.send::<Handshake>(process.map(|process| (process, violations.map(WireFd::new))))
+}
+
+pub(crate) fn connect(
+    addr: &Address,
+) -> Result<(Process, Pipe, Option<WireFd<File>>), crate::Error> {
+    let stream = UnixStream::connect_addr(&addr.to_socket_addr()?)?;
+    let mut pipe = Pipe::new(stream.into());
+    // the zygote doesn't wait for the executable to reject peers that are not
+    // allowed in, look for its reply even if it's gone by the time we send it
+    let sent = pipe.send(WireFd::new(open_executable()?));
+    let (process, violations) = match pipe.recv::<Handshake>() {
+        Ok(handshake) => handshake?,
+        Err(err) => return Err(sent.err().unwrap_or(err)),
+    };
+    Ok((process, pipe, violations))
+}
@@ -0,0 +1,125 @@
+use std::time::{Duration, Instant};
+use std::{fs, io};
+
+use serde::{Deserialize, Serialize};
+
+use crate::WireError;
+
+/// The resources used to run a task,
+/// see [`Zygote::run_with_stats()`](crate::Zygote::run_with_stats).
+///
+/// CPU time and context switches are those of the zygote thread running
+/// the task, and don't include any process or thread the task starts.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The time it took to run the task, as measured in the zygote.
+    pub wall_time: Duration,
+    /// The CPU time spent running the task in user mode.
+    pub user_time: Duration,
+    /// The CPU time spent running the task in kernel mode.
+    pub system_time: Duration,
+    /// How much the task raised the peak resident set size of the zygote, in bytes.
+    pub max_rss_delta: u64,
+    /// The number of times the task yielded the CPU, e.g., to wait for I/O.
+    pub voluntary_context_switches: u64,
+    /// The number of times the task was preempted.
+    pub involuntary_context_switches: u64,
+    /// The bytes sent to the zygote, including the arguments of the task.
+    pub bytes_sent: u64,
+    /// The bytes received from the zygote, including the result of the task.
+    pub bytes_received: u64,
+}
+
+/// Run `f`, measuring the resources the calling thread uses.
+/// The traffic is left for the caller to fill in.
+pub(crate) fn measure<R>(f: impl FnOnce() -> R) -> Result<(R, Stats), WireError> {
+    let fail = |err| WireError::from(err).context("measure the resource usage");
+    let before = rusage().map_err(fail)?;
+    let start = Instant::now();
+    let ret = f();
+    let wall_time = start.elapsed();
+    let after = rusage().map_err(fail)?;
+    let stats = Stats {
+        wall_time,
+        user_time: duration(after.ru_utime).saturating_sub(duration(before.ru_utime)),
+        system_time: duration(after.ru_stime).saturating_sub(duration(before.ru_stime)),
+        // in kilobytes
+        max_rss_delta: delta(after.ru_maxrss, before.ru_maxrss) * 1024,
+        voluntary_context_switches: delta(after.ru_nvcsw, before.ru_nvcsw),
+        involuntary_context_switches: delta(after.ru_nivcsw, before.ru_nivcsw),
+        bytes_sent: 0,
+        bytes_received: 0,
+    };
+    Ok((ret, stats))
+}
+
+fn rusage() -> io::Result<libc::rusage> {
+    let mut usage = unsafe { std::mem::zeroed() };
+    match unsafe { libc::getrusage(libc::RUSAGE_THREAD, &mut usage) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(usage),
+    }
+}
+
+fn duration(time: libc::timeval) -> Duration {
+    Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000)
+}
+
+fn delta(after: libc::c_long, before: libc::c_long) -> u64 {
+    after.saturating_sub(before).max(0) as u64
+}
+
+/// The memory footprint of a zygote, see [`Zygote::stats()`](crate::Zygote::stats).
+///
+/// Pages shared with other processes, e.g., with the zygote it was spawned from,
+/// are accounted in full in the RSS of each process sharing them, and
+/// proportionally to the number of processes sharing them in the PSS.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ZygoteStats {
+    /// The resident set size, in bytes.
+    pub rss: u64,
+    /// The proportional set size, in bytes.
+    pub pss: u64,
+    /// The resident pages only mapped by the zygote, and not modified, in bytes.
+    pub private_clean: u64,
+    /// The resident pages only mapped by the zygote, and modified, in bytes.
+    pub private_dirty: u64,
+    /// The resident pages also mapped by other processes, and not modified, in bytes.
+    pub shared_clean: u64,
+    /// The resident pages also mapped by other processes, and modified, in bytes.
+    pub shared_dirty: u64,
+    /// The number of threads of the zygote.
+    pub threads: u64,
+    /// The number of tasks the zygote has run.
+    pub tasks: u64,
+}
+
+/// Read the memory footprint of the process `pid` from procfs.
+/// The number of tasks is left for the caller to fill in.
+pub(crate) fn footprint(pid: libc::pid_t) -> io::Result<ZygoteStats> {
+    let mut stats = ZygoteStats::default();
+    let status = fs::read_to_string(format!("/proc/{pid}/status"))?;
+    let smaps = fs::read_to_string(format!("/proc/{pid}/smaps_rollup"))?;
+    for line in status.lines().chain(smaps.lines()) {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let field = match key {
+            "Threads" => &mut stats.threads,
+            "Rss" => &mut stats.rss,
+            "Pss" => &mut stats.pss,
+            "Private_Clean" => &mut stats.private_clean,
+            "Private_Dirty" => &mut stats.private_dirty,
+            "Shared_Clean" => &mut stats.shared_clean,
+            "Shared_Dirty" => &mut stats.shared_dirty,
+            _ => continue,
+        };
+        // sizes are in kilobytes
+        let (value, scale) = match value.trim().strip_suffix(" kB") {
+            Some(value) => (value, 1024),
+            None => (value.trim(), 1),
+        };
+        *field = value.parse::<u64>().map_err(io::Error::other)? * scale;
+    }
+    Ok(stats)
+}
@@ -1,8 +1,17 @@
-use std::io::{read_to_string, Write};
+// some tests predate the lints of recent toolchains
+#![allow(clippy::useless_conversion)]
+
+use std::io::{read_to_string, Read, Write};
 use std::net::Shutdown;
+use std::os::fd::OwnedFd;
 use std::os::unix::net::UnixStream;
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::time::Duration;
 
-use zygote::{WireError, WireFd, Zygote};
+use zygote::{DropPolicy, Error, Resource, Transfer, WireError, WireFd, Zygote};
 
 fn getppid() -> u32 {
     unsafe { libc::getppid() as u32 }
@@ -25,6 +34,12 @@ fn does_error(_: ()) -> Result<(), WireError> {
     Ok(())
 }
 
+fn spin(_: ()) {
+    loop {
+        std::hint::spin_loop();
+    }
+}
+
 fn write_to_pipes(pipes: Vec<WireFd<UnixStream>>) {
     for (i, mut pipe) in pipes.into_iter().enumerate() {
         write!(pipe, "hello world {i}!").unwrap();
@@ -127,3 +142,433 @@ fn nested_zygote() {
     assert_eq!(zyg_ppid, zygzyg_ppid);
     assert_eq!(pid, zygzyg_ppid);
 }
+
+#[test]
+fn child_zygote() {
+    let zygote = Zygote::new();
+    let zyg_pid = zygote.run(|_| getpid(), ());
+
+    let child = zygote.spawn_child();
+    let child_pid = child.run(|_| getpid(), ());
+    let child_ppid = child.run(|_| getppid(), ());
+
+    assert_ne!(zyg_pid, child_pid);
+    assert_eq!(zyg_pid, child_ppid);
+
+    // killing the parent zygote takes the child down with it
+    drop(zygote);
+    child.try_run(|_| getpid(), ()).unwrap_err();
+}
+
+#[test]
+fn send_zygote() {
+    let zygote1 = Zygote::new();
+    let zygote2 = Zygote::new();
+    let pid2 = zygote2.run(|_| getpid(), ());
+
+    let (pid, zygote2) = zygote1.run(
+        |z: Transfer| {
+            let z = z.into_zygote();
+            (z.run(|_| getpid(), ()), z.transfer())
+        },
+        zygote2.transfer(),
+    );
+    assert_eq!(pid, pid2);
+    // the handle came back, along with the ownership of the zygote
+    let zygote2 = zygote2.into_zygote();
+    assert_eq!(zygote2.run(|_| getpid(), ()), pid2);
+}
+
+struct Unserializable;
+
+impl serde::Serialize for Unserializable {
+    fn serialize<S: serde::Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+        Err(serde::ser::Error::custom("unserializable"))
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Unserializable {
+    fn deserialize<D: serde::Deserializer<'de>>(_: D) -> Result<Self, D::Error> {
+        Ok(Unserializable)
+    }
+}
+
+#[test]
+fn failed_transfer() {
+    let zygote1 = Zygote::new();
+    let zygote2 = Zygote::new();
+    let pid2 = zygote2.pid().unwrap() as libc::pid_t;
+
+    // the zygote is serialized, but never sent, so it's still ours to kill
+    let res = zygote1.try_run(
+        |_: (Transfer, Unserializable)| {},
+        (zygote2.transfer(), Unserializable),
+    );
+    assert!(matches!(res, Err(Error::Encode(_))));
+    assert_eq!(unsafe { libc::kill(pid2, 0) }, -1);
+}
+
+static EXIT_MARKER: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+extern "C" fn create_exit_marker() {
+    if let Some(path) = EXIT_MARKER.lock().unwrap().as_ref() {
+        std::fs::write(path, "bye").unwrap();
+    }
+}
+
+extern "C" fn hang() {
+    loop {
+        std::thread::sleep(Duration::from_secs(1));
+    }
+}
+
+#[test]
+fn shutdown() {
+    let zygote = Zygote::new();
+    let status = zygote.shutdown(Duration::from_secs(5)).unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn shutdown_timeout() {
+    let zygote = Zygote::new();
+    zygote.run(|_| unsafe { libc::atexit(hang) }, ());
+    let status = zygote.shutdown(Duration::from_millis(100)).unwrap();
+    assert_eq!(status.signal(), Some(libc::SIGKILL));
+}
+
+#[test]
+fn graceful_drop() {
+    let path = std::env::temp_dir().join(format!("zygote-test-graceful-{}", getpid()));
+    let zygote = Zygote::builder()
+        .drop_policy(DropPolicy::Graceful(Duration::from_secs(5)))
+        .build()
+        .unwrap();
+    zygote.run(
+        |path: PathBuf| {
+            *EXIT_MARKER.lock().unwrap() = Some(path);
+            unsafe { libc::atexit(create_exit_marker) }
+        },
+        &path,
+    );
+
+    drop(zygote);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "bye");
+    std::fs::remove_file(path).unwrap();
+}
+
+#[allow(clippy::zombie_processes)]
+fn spawn_sleepers(fd: WireFd<OwnedFd>) {
+    // a process that gets orphaned
+    Command::new("sh")
+        .args(["-c", "sleep 1000 &"])
+        .stdout(Stdio::from(fd.try_clone().unwrap()))
+        .status()
+        .unwrap();
+    // a direct child of the zygote
+    Command::new("sleep")
+        .arg("1000")
+        .stdout(Stdio::from(fd.into_inner()))
+        .spawn()
+        .unwrap();
+}
+
+fn assert_closed(mut reader: UnixStream) {
+    // the sleepers hold the other end, reading returns EOF once they are gone
+    reader
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    assert_eq!(reader.read(&mut [0]).unwrap(), 0);
+}
+
+#[test]
+fn kill_tree() {
+    let (reader, writer) = UnixStream::pair().unwrap();
+    let zygote = Zygote::builder().kill_tree(true).build().unwrap();
+    zygote.run(spawn_sleepers, WireFd::new(OwnedFd::from(writer)));
+
+    drop(zygote);
+    assert_closed(reader);
+}
+
+#[test]
+fn kill_tree_pid() {
+    // even when it could create a pid namespace, the zygote stays in ours
+    if unsafe { libc::geteuid() } != 0 {
+        return;
+    }
+    let zygote = Zygote::builder().kill_tree(true).build().unwrap();
+    let pid = zygote.run(|_| std::process::id(), ());
+    assert_eq!(pid, zygote.pid().unwrap());
+
+    // signals with a default action are delivered, unlike for the init of a namespace
+    assert_eq!(unsafe { libc::kill(pid as _, libc::SIGTERM) }, 0);
+    let res = zygote.try_run(|_| (), ());
+    assert!(matches!(res, Err(Error::Died(status)) if status.signal() == Some(libc::SIGTERM)));
+}
+
+#[test]
+fn kill_tree_shutdown() {
+    let parent = Zygote::builder().kill_tree(true).build().unwrap();
+    let zygote = Zygote::builder()
+        .kill_tree(true)
+        .spawn_child(&parent)
+        .unwrap();
+    // create the pair after forking the zygotes, so that they don't inherit it
+    let (reader, writer) = UnixStream::pair().unwrap();
+    zygote.run(spawn_sleepers, WireFd::new(OwnedFd::from(writer)));
+
+    zygote.shutdown(Duration::from_secs(5)).unwrap();
+    assert_closed(reader);
+}
+
+fn parent_death_signal(_: ()) -> i32 {
+    let mut signal = 0;
+    unsafe { libc::prctl(libc::PR_GET_PDEATHSIG, &mut signal) };
+    signal
+}
+
+#[test]
+fn worker_thread_parent_death() {
+    // the signal would be sent as soon as the test thread exits
+    let zygote = Zygote::new();
+    assert_eq!(zygote.run(parent_death_signal, ()), 0);
+    let sibling = zygote.spawn();
+    assert_eq!(sibling.run(parent_death_signal, ()), 0);
+
+    let zygote = Zygote::builder()
+        .parent_death_signal(Some(libc::SIGTERM))
+        .build()
+        .unwrap();
+    assert_eq!(zygote.run(parent_death_signal, ()), libc::SIGTERM);
+    let sibling = zygote.spawn();
+    assert_eq!(sibling.run(parent_death_signal, ()), libc::SIGTERM);
+}
+
+#[test]
+fn parent_death() {
+    let parent = Zygote::new();
+    let zygote = parent.run(|_| Zygote::new().transfer(), ()).into_zygote();
+    let sibling = zygote.spawn();
+    let orphan = parent.run(
+        |_| {
+            let zygote = Zygote::builder().parent_death_signal(None).build();
+            zygote.unwrap().transfer()
+        },
+        (),
+    );
+    let orphan = orphan.into_zygote();
+
+    drop(parent);
+    assert!(zygote.try_run(|_| (), ()).is_err());
+    assert!(sibling.try_run(|_| (), ()).is_err());
+    orphan.run(|_| (), ());
+}
+
+#[test]
+fn rlimit() {
+    let zygote = Zygote::builder()
+        .rlimit(Resource::OpenFiles, 64, 64)
+        .build()
+        .unwrap();
+    let limit = zygote.run(
+        |_| {
+            let mut limit = libc::rlimit {
+                rlim_cur: 0,
+                rlim_max: 0,
+            };
+            unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) };
+            limit.rlim_cur
+        },
+        (),
+    );
+    assert_eq!(limit, 64);
+
+    let zygote = Zygote::builder()
+        .rlimit(Resource::CpuTime, 1, 2)
+        .build()
+        .unwrap();
+    let err = zygote.try_run(spin, ()).unwrap_err();
+    let Error::Died(status) = err else {
+        panic!("unexpected error: {err}");
+    };
+    assert_eq!(status.signal(), Some(libc::SIGXCPU));
+}
+
+#[test]
+fn task_rlimit() {
+    let zygote = Zygote::builder()
+        .rlimit(Resource::OpenFiles, 64, 64)
+        .build()
+        .unwrap();
+    let open_files = |_| {
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) };
+        limit.rlim_cur
+    };
+    let limits = [(Resource::OpenFiles, 32, 64)];
+    assert_eq!(zygote.run_with_rlimits(&limits, open_files, ()), 32);
+    assert_eq!(zygote.run(open_files, ()), 64);
+
+    let limits = [(Resource::CpuTime, 1, 2)];
+    let err = zygote.try_run_with_rlimits(&limits, spin, ()).unwrap_err();
+    let Error::Died(status) = err else {
+        panic!("unexpected error: {err}");
+    };
+    assert_eq!(status.signal(), Some(libc::SIGXCPU));
+    zygote.run(|_| (), ());
+
+    // the soft limit can't be larger than the hard limit
+    let limits = [(Resource::CpuTime, 2, 1)];
+    let err = zygote.try_run_with_rlimits(&limits, |_| (), ());
+    assert!(err.unwrap_err().to_string().contains("CpuTime"));
+}
+
+#[test]
+fn rlimit_failure() {
+    // the soft limit can't be larger than the hard limit
+    let err = Zygote::builder()
+        .rlimit(Resource::CpuTime, 2, 1)
+        .build()
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("CpuTime"));
+}
+
+#[test]
+fn wire_error_errno() {
+    let err = Zygote::global()
+        .run(
+            |_| -> Result<(), WireError> {
+                std::fs::read("/does/not/exist")?;
+                Ok(())
+            },
+            (),
+        )
+        .unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENOENT));
+
+    let err = Zygote::global().run(does_error, ()).unwrap_err();
+    assert_eq!(err.raw_os_error(), None);
+}
+
+#[test]
+fn run_captured() {
+    let zygote = Zygote::new();
+    let (ret, output) = zygote.run_captured(
+        |name: String| {
+            // the test harness captures the output of print! in this thread,
+            // so write to the streams directly
+            write!(std::io::stdout(), "hello ").unwrap();
+            writeln!(std::io::stdout(), "{name}").unwrap();
+            writeln!(std::io::stderr(), "oops").unwrap();
+            // processes started by the task share its output
+            Command::new("sh")
+                .args(["-c", "echo child"])
+                .status()
+                .unwrap();
+            name.len()
+        },
+        "world",
+    );
+    assert_eq!(ret, 5);
+    assert_eq!(output.stdout, b"hello world\nchild\n");
+    assert_eq!(output.stderr, b"oops\n");
+
+    // the output is kept, and the streams restored, even if the task panics
+    let res = zygote.try_run_captured::<_, ()>(
+        |_| {
+            write!(std::io::stdout(), "before").unwrap();
+            panic!("sorry")
+        },
+        (),
+    );
+    let Err(Error::Wire(err)) = res else {
+        panic!("unexpected result {res:?}");
+    };
+    assert!(err.description().contains("sorry"));
+    let output = err.source().unwrap().description();
+    assert!(
+        output.contains("\"before\" to its standard output"),
+        "{output}"
+    );
+    let ((), output) = zygote.run_captured(|_| write!(std::io::stdout(), "again").unwrap(), ());
+    assert_eq!(output.stdout, b"again");
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn introspection() {
+    let zygote = Zygote::new();
+    let pid = zygote.run(|_| getpid(), ());
+    assert_eq!(zygote.pid().unwrap(), pid);
+    assert!(zygote.pidfd().is_some());
+
+    // allocate memory in the zygote, and share it with a zygote spawned from it
+    zygote.run(|_| Vec::leak(vec![1u8; 32 << 20]).len(), ());
+    let child = zygote.spawn();
+    let stats = zygote.stats().unwrap();
+    assert_eq!(stats.tasks, 3);
+    assert_eq!(stats.threads, 1);
+    assert!(stats.rss >= 32 << 20, "{stats:?}");
+    assert!(stats.shared_dirty >= 32 << 20, "{stats:?}");
+    assert!(stats.pss < stats.rss, "{stats:?}");
+
+    // the spawned zygote starts counting from scratch
+    let stats = child.stats().unwrap();
+    assert_eq!(stats.tasks, 0);
+    assert!(stats.shared_dirty >= 32 << 20, "{stats:?}");
+    assert!(stats.private_dirty < 32 << 20, "{stats:?}");
+}
+
+#[test]
+fn run_with_stats() {
+    let zygote = Zygote::new();
+    let (len, stats) = zygote.run_with_stats(
+        |data: Vec<u8>| {
+            // burn some CPU, and grow the peak memory usage
+            let start = std::time::Instant::now();
+            while start.elapsed() < Duration::from_millis(50) {}
+            let buffer = vec![1u8; 32 << 20];
+            std::hint::black_box(buffer).len() + data.len()
+        },
+        vec![0u8; 1 << 20],
+    );
+    assert_eq!(len, (32 << 20) + (1 << 20));
+    assert!(stats.wall_time >= Duration::from_millis(50));
+    assert!(stats.user_time + stats.system_time >= Duration::from_millis(20));
+    assert!(stats.max_rss_delta >= 16 << 20, "{stats:?}");
+    assert!(stats.bytes_sent >= 1 << 20);
+    assert!(stats.bytes_received > 0 && stats.bytes_received < 1 << 10);
+
+    // the stats of a task don't include those of the previous tasks
+    let ((), stats) = zygote.run_with_stats(|_| (), ());
+    assert!(stats.max_rss_delta < 16 << 20);
+    assert!(stats.bytes_sent < 1 << 10);
+}
+
+#[test]
+fn wire_error_conversions() {
+    // errors don't need to be 'static
+    #[derive(Debug)]
+    struct Borrowed<'a>(&'a str);
+
+    impl std::fmt::Display for Borrowed<'_> {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str(self.0)
+        }
+    }
+
+    impl std::error::Error for Borrowed<'_> {}
+
+    let msg = String::from("borrowed error");
+    let err = WireError::from(Borrowed(&msg));
+    assert_eq!(err.to_string(), msg);
+    assert_eq!(err.raw_os_error(), None);
+
+    let err = WireError::from(nix::errno::Errno::EACCES);
+    assert_eq!(err.raw_os_error(), Some(libc::EACCES));
+}
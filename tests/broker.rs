@@ -0,0 +1,120 @@
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use zygote::{Broker, BrokerPolicy, WireError, Zygote};
+
+fn open((path, flags): (PathBuf, i32)) -> Result<String, WireError> {
+    let mut file = Broker::current().unwrap().open(path, flags)?;
+    let mut content = String::new();
+    if flags & libc::O_ACCMODE == libc::O_RDONLY {
+        file.read_to_string(&mut content)?;
+    } else {
+        file.write_all(b"hello")?;
+    }
+    Ok(content)
+}
+
+fn read(path: PathBuf) -> Result<String, WireError> {
+    open((path, libc::O_RDONLY))
+}
+
+#[test]
+fn broker() {
+    let dir = std::env::temp_dir().join(format!("zygote-test-broker-{}", std::process::id()));
+    let ro = dir.join("ro");
+    let rw = dir.join("rw");
+    std::fs::create_dir_all(&ro).unwrap();
+    std::fs::create_dir_all(&rw).unwrap();
+    std::fs::write(ro.join("file.txt"), "hello").unwrap();
+    std::fs::write(dir.join("secret.txt"), "secret").unwrap();
+    std::os::unix::fs::symlink(dir.join("secret.txt"), ro.join("link.txt")).unwrap();
+
+    let policy = BrokerPolicy::new().read_only(&ro).read_write(&rw);
+    let broker = Broker::new(policy).unwrap();
+    let zygote = Zygote::builder().broker(broker).build().unwrap();
+
+    assert_eq!(zygote.run(read, ro.join("file.txt")).unwrap(), "hello");
+
+    let flags = libc::O_WRONLY | libc::O_CREAT;
+    zygote.run(open, (rw.join("file.txt"), flags)).unwrap();
+    assert_eq!(std::fs::read(rw.join("file.txt")).unwrap(), b"hello");
+
+    let denied = [
+        (ro.join("file.txt"), libc::O_WRONLY),
+        (ro.join("file.txt"), libc::O_RDONLY | libc::O_TRUNC),
+        (ro.join("file.txt"), libc::O_RDONLY | libc::O_PATH),
+        (ro.join("../secret.txt"), libc::O_RDONLY),
+        (ro.join("link.txt"), libc::O_RDONLY),
+        (dir.join("secret.txt"), libc::O_RDONLY),
+        (PathBuf::from("ro/file.txt"), libc::O_RDONLY),
+    ];
+    for args in denied {
+        let err = zygote.run(open, &args).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EACCES), "{args:?}");
+    }
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn broker_argument() {
+    let broker = Broker::new(BrokerPolicy::new().read_only("/proc/self")).unwrap();
+    let zygote = Zygote::new();
+
+    // the broker opens the file, so `self` is the broker process
+    let status = zygote.run(
+        |broker: Broker| -> Result<String, WireError> {
+            let mut file = broker.open("/proc/self/status", libc::O_RDONLY)?;
+            let mut content = String::new();
+            file.read_to_string(&mut content)?;
+            Ok(content)
+        },
+        &broker,
+    );
+    let status = status.unwrap();
+    let pid = status.lines().find_map(|line| line.strip_prefix("Pid:"));
+    let pid: u32 = pid.unwrap().trim().parse().unwrap();
+    // the broker runs in its own process
+    assert_ne!(pid, std::process::id());
+    assert_ne!(pid, zygote.pid().unwrap());
+
+    // the broker stops once every handle to it is gone,
+    // though it might not be reaped right away
+    drop((broker, zygote));
+    let alive = || {
+        let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).unwrap_or_default();
+        !stat.is_empty() && !stat.contains(") Z ")
+    };
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while alive() {
+        assert!(Instant::now() < deadline, "the broker outlived its handles");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn spawned_broker() {
+    if unsafe { libc::geteuid() } != 0 {
+        return;
+    }
+
+    // the broker runs with the privileges of the zygote it's spawned from
+    let policy = BrokerPolicy::new().read_only("/etc");
+    let zygote = Zygote::builder().uid(65534).gid(65534).build().unwrap();
+    let broker = Broker::spawn(policy.clone(), &zygote).unwrap();
+    let err = broker.open("/etc/shadow", libc::O_RDONLY).err().unwrap();
+    assert_eq!(err.raw_os_error(), Some(libc::EACCES));
+
+    let broker = Broker::new(policy).unwrap();
+    broker.open("/etc/shadow", libc::O_RDONLY).unwrap();
+}
+
+#[test]
+fn inherited_broker() {
+    let broker = Broker::new(BrokerPolicy::new().read_only("/etc")).unwrap();
+    let zygote = Zygote::builder().broker(broker).build().unwrap();
+    let child = zygote.spawn_child();
+    assert!(child.run(|_| Broker::current().is_some(), ()));
+    assert!(!Zygote::new().run(|_| Broker::current().is_some(), ()));
+}
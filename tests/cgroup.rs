@@ -0,0 +1,70 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use zygote::{Cgroup, Zygote, ZygoteBuilder};
+
+/// Create a new group beneath the group of the current process,
+/// if we are allowed to.
+fn cgroup(name: &str) -> Option<(Cgroup, PathBuf)> {
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo").unwrap();
+    let mount = mountinfo
+        .lines()
+        .find(|line| line.contains(" - cgroup2 "))?
+        .split(' ')
+        .nth(4)?;
+    let cgroup = std::fs::read_to_string("/proc/self/cgroup").unwrap();
+    let cgroup = cgroup.lines().find_map(|line| line.strip_prefix("0::/"))?;
+    let path = PathBuf::from(mount)
+        .join(cgroup)
+        .join(format!("zygote-test-{name}-{}", std::process::id()));
+    Some((Cgroup::create(&path).ok()?, path))
+}
+
+#[test]
+fn into_cgroup() {
+    let Some((cgroup, path)) = cgroup("into") else {
+        return;
+    };
+
+    let zygote = Zygote::builder().cgroup(cgroup.clone()).build().unwrap();
+    let pid = zygote.run(|_| std::process::id(), ());
+    assert_eq!(cgroup.procs().unwrap(), [pid]);
+
+    // processes spawned from the zygote stay in the group
+    let child = zygote.spawn_child();
+    let child_pid = child.run(|_| std::process::id(), ());
+    assert!(cgroup.procs().unwrap().contains(&child_pid));
+
+    zygote.run(|_| (0..10_000_000u64).sum::<u64>(), ());
+    assert!(cgroup.cpu_usage().unwrap() > Duration::ZERO);
+
+    drop(child);
+    drop(zygote);
+    std::fs::remove_dir(path).unwrap();
+}
+
+#[test]
+fn limits() {
+    let Some((cgroup, path)) = cgroup("limits") else {
+        return;
+    };
+    // the controllers might not be enabled for the group
+    if cgroup.set_pids_max(Some(2)).is_ok() {
+        let zygote = Zygote::builder().cgroup(cgroup.clone()).build().unwrap();
+        let child = zygote.spawn_child();
+        assert_eq!(cgroup.pids_current().unwrap(), 2);
+        assert!(ZygoteBuilder::new().spawn_child(&zygote).is_err());
+        drop(child);
+    }
+    if cgroup.set_memory_max(Some(64 << 20)).is_ok() {
+        let zygote = Zygote::builder().cgroup(cgroup.clone()).build().unwrap();
+        zygote.run(|_| drop(vec![1u8; 1 << 20]), ());
+        assert!(cgroup.memory_current().unwrap() > 0);
+    }
+    std::fs::remove_dir(path).unwrap();
+}
+
+#[test]
+fn missing_cgroup() {
+    assert!(Cgroup::open("/does/not/exist").is_err());
+}
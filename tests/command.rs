@@ -0,0 +1,242 @@
+use std::io::{read_to_string, BufRead as _, BufReader, Write as _};
+use std::os::fd::OwnedFd;
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::ExitStatusExt;
+use std::time::{Duration, Instant};
+
+use zygote::{Command, Stdio, Zygote};
+
+/// Run `command`, and get what it writes to fd 3.
+fn output(zygote: &Zygote, command: Command) -> String {
+    let (reader, writer) = UnixStream::pair().unwrap();
+    let mut child = command.fd(3, OwnedFd::from(writer)).spawn(zygote).unwrap();
+    assert!(child.wait().unwrap().success());
+    read_to_string(reader).unwrap()
+}
+
+fn sh(script: &str) -> Command {
+    Command::new("sh").args(["-c", script])
+}
+
+#[test]
+fn exit_status() {
+    let zygote = Zygote::new();
+    let mut child = sh("exit 3").spawn(&zygote).unwrap();
+    assert_eq!(child.wait().unwrap().code(), Some(3));
+    // the status is kept once collected
+    assert_eq!(child.try_wait().unwrap().unwrap().code(), Some(3));
+}
+
+#[test]
+fn kill() {
+    let zygote = Zygote::new();
+    let mut child = Command::new("sleep").arg("1000").spawn(&zygote).unwrap();
+    assert!(child.try_wait().unwrap().is_none());
+    child.kill().unwrap();
+    assert_eq!(child.wait().unwrap().signal(), Some(libc::SIGKILL));
+}
+
+#[test]
+fn exec_error() {
+    let zygote = Zygote::new();
+    let Err(zygote::Error::Wire(err)) = Command::new("does-not-exist").spawn(&zygote) else {
+        panic!("expected an error");
+    };
+    assert_eq!(err.raw_os_error(), Some(libc::ENOENT));
+
+    let res = sh("true").current_dir("/does/not/exist").spawn(&zygote);
+    let Err(zygote::Error::Wire(err)) = res else {
+        panic!("expected an error");
+    };
+    assert_eq!(err.raw_os_error(), Some(libc::ENOENT));
+
+    // the zygote is still usable
+    let mut child = sh("true").spawn(&zygote).unwrap();
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn environment() {
+    let zygote = Zygote::new();
+    let command = sh(r#"echo "$FOO $(pwd)" >&3"#)
+        .env("FOO", "foo")
+        .current_dir("/");
+    assert_eq!(output(&zygote, command), "foo /\n");
+
+    let command = sh(r#"echo "${FOO-unset} ${HOME-unset}" >&3"#)
+        .env_clear()
+        .env("FOO", "bar");
+    assert_eq!(output(&zygote, command), "bar unset\n");
+}
+
+#[test]
+fn fds() {
+    let zygote = Zygote::new();
+    let (reader3, writer3) = UnixStream::pair().unwrap();
+    let (reader4, writer4) = UnixStream::pair().unwrap();
+    // swap the fds around, so that each target is also a source
+    let mut child = sh("echo three >&3; echo four >&4")
+        .fd(4, OwnedFd::from(writer4))
+        .fd(3, OwnedFd::from(writer3))
+        .spawn(&zygote)
+        .unwrap();
+    assert!(child.wait().unwrap().success());
+    assert_eq!(read_to_string(reader3).unwrap(), "three\n");
+    assert_eq!(read_to_string(reader4).unwrap(), "four\n");
+}
+
+#[test]
+fn signals() {
+    let zygote = Zygote::new();
+    let command = sh("grep -E '^(SigBlk|SigIgn)' /proc/self/status >&3");
+    let status = output(&zygote, command);
+    let mask = |name| {
+        let line = status.lines().find_map(|l| l.strip_prefix(name)).unwrap();
+        u64::from_str_radix(line.trim(), 16).unwrap()
+    };
+    assert_eq!(mask("SigBlk:"), 0);
+    // rust ignores SIGPIPE, but the command shouldn't
+    assert_eq!(mask("SigIgn:") & (1 << (libc::SIGPIPE - 1)), 0);
+}
+
+#[test]
+fn parent_death_signal() {
+    let parent = Zygote::new();
+    let zygote = parent.spawn_child();
+    let child = Command::new("sleep")
+        .arg("1000")
+        .parent_death_signal(Some(libc::SIGKILL))
+        .spawn(&zygote)
+        .unwrap();
+    let pid = child.id();
+    drop(zygote);
+
+    // once orphaned, the command might not be reaped right away
+    let alive = || {
+        let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).unwrap_or_default();
+        !stat.is_empty() && !stat.contains(") Z ")
+    };
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while alive() {
+        assert!(Instant::now() < deadline, "the command outlived the zygote");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn stdio() {
+    let zygote = Zygote::new();
+    let mut child = Command::new("cat")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn(&zygote)
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"hello").unwrap();
+    drop(stdin);
+    let stdout = read_to_string(child.stdout.take().unwrap()).unwrap();
+    assert_eq!(stdout, "hello");
+    assert!(child.stderr.is_none());
+    assert!(child.wait().unwrap().success());
+
+    let (reader, writer) = UnixStream::pair().unwrap();
+    let mut child = sh("echo oops >&2")
+        .stderr(OwnedFd::from(writer))
+        .spawn(&zygote)
+        .unwrap();
+    assert!(child.wait().unwrap().success());
+    assert_eq!(read_to_string(reader).unwrap(), "oops\n");
+}
+
+#[test]
+fn close_fds() {
+    let zygote = Zygote::new();
+    // leak a file descriptor without close-on-exec in the zygote
+    let fd = zygote.run(|_| unsafe { libc::fcntl(0, libc::F_DUPFD, 10) }, ());
+    assert_ne!(fd, -1);
+    let script = format!("[ -e /proc/self/fd/{fd} ] && echo open >&3 || echo closed >&3");
+    assert_eq!(output(&zygote, sh(&script)), "closed\n");
+    // unless it's mapped
+    let script = "[ -e /proc/self/fd/4 ] && echo open >&3 || echo closed >&3";
+    let null = OwnedFd::from(std::fs::File::open("/dev/null").unwrap());
+    assert_eq!(output(&zygote, sh(script).fd(4, null)), "open\n");
+}
+
+#[test]
+fn pty() {
+    let zygote = Zygote::new();
+    let mut child = sh("read line; echo \"got $line\"; stty size")
+        .pty(24, 80)
+        .spawn(&zygote)
+        .unwrap();
+    let mut pty = child.pty.as_ref().unwrap().try_clone().unwrap();
+    let reader = BufReader::new(pty.try_clone().unwrap());
+
+    child.resize_pty(30, 100).unwrap();
+    pty.write_all(b"hello\n").unwrap();
+
+    // skip the echo of what we typed
+    let mut lines = reader.lines().map(Result::unwrap);
+    assert_eq!(lines.nth(1).unwrap(), "got hello");
+    assert_eq!(lines.next().unwrap(), "30 100");
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn pty_session() {
+    let zygote = Zygote::new();
+    // the command leads its own session, with the terminal as its controlling terminal
+    let script = r#"echo "$$ $(ps -o sid=,tpgid= -p $$) $(tty)" >&3"#;
+    let command = sh(script).pty(24, 80).foreground(true);
+    let output = output(&zygote, command);
+    let [pid, sid, tpgid, tty] = output.split_whitespace().collect::<Vec<_>>()[..] else {
+        panic!("unexpected output {output:?}");
+    };
+    assert_eq!(pid, sid);
+    assert_eq!(pid, tpgid);
+    assert!(tty.starts_with("/dev/pts/"));
+}
+
+fn state(pid: u32) -> char {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).unwrap();
+    stat.rsplit_once(") ").unwrap().1.chars().next().unwrap()
+}
+
+#[test]
+fn process_group() {
+    let zygote = Zygote::new();
+    let sleep = || Command::new("sleep").arg("1000");
+    let mut first = sleep().process_group(0).spawn(&zygote).unwrap();
+    let mut second = sleep()
+        .process_group(first.id() as _)
+        .spawn(&zygote)
+        .unwrap();
+    let mut other = sleep().spawn(&zygote).unwrap();
+    assert!(other.signal_group(libc::SIGKILL).is_err());
+
+    first.signal_group(libc::SIGSTOP).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while state(first.id()) != 'T' || state(second.id()) != 'T' {
+        assert!(Instant::now() < deadline, "the group didn't stop");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(state(other.id()), 'S');
+
+    first.signal_group(libc::SIGCONT).unwrap();
+    first.signal_group(libc::SIGTERM).unwrap();
+    assert_eq!(first.wait().unwrap().signal(), Some(libc::SIGTERM));
+    assert_eq!(second.wait().unwrap().signal(), Some(libc::SIGTERM));
+    other.kill().unwrap();
+    other.wait().unwrap();
+}
+
+#[test]
+fn setsid() {
+    let zygote = Zygote::new();
+    let script = r#"echo "$$ $(ps -o pgid= -p $$) $(ps -o sid= -p $$)" >&3"#;
+    let output = output(&zygote, sh(script).setsid(true));
+    let ids: Vec<&str> = output.split_whitespace().collect();
+    assert_eq!(ids.len(), 3);
+    assert!(ids.iter().all(|id| *id == ids[0]));
+}
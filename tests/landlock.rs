@@ -0,0 +1,75 @@
+#![cfg(feature = "landlock")]
+
+use std::path::PathBuf;
+
+use zygote::{Broker, BrokerPolicy, LandlockPolicy, WireError, Zygote};
+
+fn read(path: PathBuf) -> Result<String, WireError> {
+    Ok(std::fs::read_to_string(path)?)
+}
+
+fn write(path: PathBuf) -> Result<(), WireError> {
+    Ok(std::fs::write(path, "hello")?)
+}
+
+#[test]
+fn landlock() {
+    if !LandlockPolicy::is_supported() {
+        return;
+    }
+
+    let dir = std::env::temp_dir().join(format!("zygote-test-landlock-{}", std::process::id()));
+    let ro = dir.join("ro");
+    let rw = dir.join("rw");
+    std::fs::create_dir_all(&ro).unwrap();
+    std::fs::create_dir_all(&rw).unwrap();
+    std::fs::write(ro.join("file.txt"), "hello").unwrap();
+
+    let zygote = Zygote::builder()
+        .landlock(LandlockPolicy::new().read_only(&ro).read_write(&rw))
+        .build()
+        .unwrap();
+
+    assert_eq!(zygote.run(read, ro.join("file.txt")).unwrap(), "hello");
+    zygote.run(write, rw.join("file.txt")).unwrap();
+
+    let err = zygote.run(write, ro.join("file.txt")).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EACCES));
+
+    let err = zygote
+        .run(read, PathBuf::from("/etc/hostname"))
+        .unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EACCES));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn required() {
+    let res = Zygote::builder()
+        .landlock(LandlockPolicy::new().required(true))
+        .build();
+    assert_eq!(res.is_ok(), LandlockPolicy::is_supported());
+}
+
+#[test]
+fn broker() {
+    let broker = Broker::new(BrokerPolicy::new().read_only("/etc")).unwrap();
+    let zygote = Zygote::builder()
+        .landlock(LandlockPolicy::new())
+        .broker(broker)
+        .build()
+        .unwrap();
+
+    // the broker is not restricted by the landlock policy of the zygote
+    let res = zygote.run(
+        |_| -> Result<String, WireError> {
+            let file = Broker::current()
+                .unwrap()
+                .open("/etc/passwd", libc::O_RDONLY)?;
+            Ok(std::io::read_to_string(file)?)
+        },
+        (),
+    );
+    assert!(res.unwrap().contains("root"));
+}
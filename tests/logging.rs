@@ -0,0 +1,115 @@
+#![cfg(any(feature = "log", feature = "tracing"))]
+
+use zygote::Zygote;
+
+#[cfg(feature = "log")]
+#[test]
+fn log() {
+    use std::sync::Mutex;
+
+    use zygote::LogForwarder;
+
+    static RECORDS: Mutex<Vec<String>> = Mutex::new(vec![]);
+
+    struct Collector;
+
+    impl log::Log for Collector {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            let kvs = record.key_values();
+            let pid = kvs.get("zygote.pid".into()).map(|v| v.to_string());
+            let task = kvs.get("zygote.task".into()).map(|v| v.to_string());
+            let line = format!("{} {} {pid:?} {task:?}", record.level(), record.args());
+            RECORDS.lock().unwrap().push(line);
+        }
+
+        fn flush(&self) {}
+    }
+
+    log::set_boxed_logger(Box::new(LogForwarder::new(Collector))).unwrap();
+    log::set_max_level(log::LevelFilter::Info);
+
+    let zygote = Zygote::new();
+    let pid = zygote.run(|_| std::process::id(), ());
+    log::warn!("from the parent");
+    let line = line!() + 1;
+    zygote.run(
+        |_| {
+            log::warn!("from the zygote");
+            log::debug!("filtered out");
+        },
+        (),
+    );
+    // records emitted outside of a task are not forwarded
+    zygote.run(
+        |_| {
+            std::thread::spawn(|| log::warn!("from a thread"))
+                .join()
+                .is_ok()
+        },
+        (),
+    );
+
+    let records = RECORDS.lock().unwrap();
+    assert_eq!(records.len(), 2, "{records:?}");
+    assert_eq!(records[0], "WARN from the parent None None");
+    let task = format!("{}:{line}:12", file!());
+    assert_eq!(
+        records[1],
+        format!("WARN from the zygote Some(\"{pid}\") Some(\"{task}\")")
+    );
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn tracing() {
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let buffer = Buffer::default();
+    let writer = buffer.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(move || writer.clone())
+        .with_ansi(false)
+        .finish();
+
+    tracing::subscriber::with_default(subscriber, || {
+        let zygote = Zygote::new();
+        let pid = zygote.run(|_| std::process::id(), ());
+        let _span = tracing::info_span!("caller").entered();
+        zygote.run(
+            |_| {
+                let _span = tracing::info_span!("task", id = 7).entered();
+                let _inner = tracing::info_span!("inner").entered();
+                tracing::info!(answer = 42, "from the zygote");
+            },
+            (),
+        );
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let output = output.trim_end();
+        assert_eq!(output.lines().count(), 1, "{output}");
+        // the event is emitted in the span of the caller, and in the spans of the zygote
+        let spans = r#" caller:zygote{name="task" fields="id=7"}:zygote{name="inner"}: "#;
+        assert!(output.contains(spans), "{output}");
+        assert!(output.contains(" zygote: from the zygote answer=42 "));
+        assert!(output.contains(&format!(" zygote.pid={pid} ")));
+        assert!(output.ends_with(" zygote.target=\"logging\""));
+    });
+}
@@ -0,0 +1,55 @@
+#![cfg(feature = "metrics")]
+
+use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+use zygote::{MetricsObserver, Zygote};
+
+#[test]
+fn metrics() {
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    recorder.install().unwrap();
+    zygote::set_observer(MetricsObserver).unwrap();
+
+    let zygote = Zygote::new();
+    let line = line!() + 1;
+    let task = || zygote.try_run(|fail: bool| assert!(!fail), false);
+    task().unwrap();
+    task().unwrap();
+    let res = zygote.try_run(|fail: bool| assert!(!fail), true);
+    assert!(res.is_err());
+
+    let metrics = snapshotter.snapshot().into_vec();
+    // the task label is the location of the task, `file:line:column`
+    let get = |name: &str, line: Option<u32>| {
+        let prefix = line.map(|line| format!("{}:{line}:", file!()));
+        let (_, _, _, value) = metrics
+            .iter()
+            .find(|(key, ..)| {
+                let key = key.key();
+                let task = key.labels().find(|l| l.key() == "task");
+                let matches = match (task, &prefix) {
+                    (Some(task), Some(prefix)) => task.value().starts_with(prefix),
+                    (task, prefix) => task.is_none() && prefix.is_none(),
+                };
+                key.name() == name && matches
+            })
+            .unwrap_or_else(|| panic!("{name} not found in {metrics:?}"));
+        value
+    };
+
+    assert_eq!(get("zygote_created_total", None), &DebugValue::Counter(1));
+    let started = get("zygote_tasks_started_total", Some(line));
+    assert_eq!(started, &DebugValue::Counter(2));
+    let finished = get("zygote_tasks_finished_total", Some(line));
+    assert_eq!(finished, &DebugValue::Counter(2));
+    let DebugValue::Histogram(durations) = get("zygote_task_duration_seconds", Some(line)) else {
+        panic!("expected a histogram");
+    };
+    assert_eq!(durations.len(), 2);
+    let DebugValue::Counter(bytes) = get("zygote_task_bytes_sent_total", Some(line)) else {
+        panic!("expected a counter");
+    };
+    assert!(*bytes > 0);
+    let panicked = get("zygote_tasks_panicked_total", Some(line + 3));
+    assert_eq!(panicked, &DebugValue::Counter(1));
+}
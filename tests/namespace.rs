@@ -0,0 +1,113 @@
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+
+use zygote::{Mounts, Network, WireError, Zygote, ZygoteBuilder};
+
+fn exists(path: PathBuf) -> bool {
+    path.exists()
+}
+
+fn write(path: PathBuf) -> Result<(), WireError> {
+    Ok(std::fs::write(path, "hello")?)
+}
+
+fn builder() -> Option<ZygoteBuilder> {
+    // user namespaces might not be available, e.g., in some containers
+    let supported = Zygote::builder().user_namespace(true).build().is_ok();
+    supported.then(|| Zygote::builder().user_namespace(true))
+}
+
+#[test]
+fn mounts() {
+    let Some(builder) = builder() else {
+        return;
+    };
+
+    let dir = std::env::temp_dir().join(format!("zygote-test-mounts-{}", std::process::id()));
+    let ro = dir.join("ro");
+    let rw = dir.join("rw");
+    std::fs::create_dir_all(&ro).unwrap();
+    std::fs::create_dir_all(&rw).unwrap();
+    std::fs::write(ro.join("file.txt"), "hello").unwrap();
+
+    let zygote = builder
+        .mounts(
+            Mounts::new()
+                .tmpfs("/tmp")
+                .read_only(&ro)
+                .read_write(&rw)
+                .bind(ro.join("file.txt"), "/file.txt")
+                .proc("/proc"),
+        )
+        .build()
+        .unwrap();
+
+    assert!(!zygote.run(exists, PathBuf::from("/etc")));
+    assert!(!zygote.run(exists, dir.join("secret.txt")));
+    assert!(zygote.run(exists, ro.join("file.txt")));
+    assert!(zygote.run(exists, PathBuf::from("/file.txt")));
+
+    zygote.run(write, rw.join("file.txt")).unwrap();
+    zygote.run(write, PathBuf::from("/tmp/file.txt")).unwrap();
+    assert!(rw.join("file.txt").exists());
+
+    for path in [ro.join("file.txt"), "/file.txt".into(), "/file2.txt".into()] {
+        let err = zygote.run(write, &path).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EROFS), "{path:?}");
+    }
+
+    // the zygote is the init process of its own pid namespace
+    let pid = zygote.run(|_| std::fs::read_link("/proc/self").unwrap(), ());
+    assert_eq!(pid, Path::new("1"));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn missing_source() {
+    let Some(builder) = builder() else {
+        return;
+    };
+    let err = builder
+        .mounts(Mounts::new().read_only("/does/not/exist"))
+        .build()
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("/does/not/exist"), "{err}");
+}
+
+fn connect(_: ()) -> Result<(), WireError> {
+    TcpStream::connect((Ipv4Addr::new(1, 1, 1, 1), 80))?;
+    Ok(())
+}
+
+fn connect_loopback(_: ()) -> Result<(), WireError> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    TcpStream::connect(listener.local_addr()?)?;
+    listener.accept()?;
+    Ok(())
+}
+
+#[test]
+fn no_network() {
+    let Some(builder) = builder() else {
+        return;
+    };
+    let zygote = builder.network(Network::None).build().unwrap();
+
+    let err = zygote.run(connect, ()).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENETUNREACH));
+    zygote.run(connect_loopback, ()).unwrap_err();
+}
+
+#[test]
+fn loopback_only() {
+    let Some(builder) = builder() else {
+        return;
+    };
+    let zygote = builder.network(Network::LoopbackOnly).build().unwrap();
+
+    let err = zygote.run(connect, ()).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENETUNREACH));
+    zygote.run(connect_loopback, ()).unwrap();
+}
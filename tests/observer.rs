@@ -0,0 +1,83 @@
+use std::os::fd::OwnedFd;
+use std::os::unix::process::ExitStatusExt as _;
+use std::panic::Location;
+use std::process::ExitStatus;
+use std::sync::Mutex;
+
+use zygote::{Error, Observer, TaskMetrics, WireError, WireFd, Zygote};
+
+static EVENTS: Mutex<Vec<String>> = Mutex::new(vec![]);
+
+struct Recorder;
+
+fn record(event: String) {
+    EVENTS.lock().unwrap().push(event);
+}
+
+impl Observer for Recorder {
+    fn zygote_created(&self, _: &Zygote) {
+        record("created".into());
+    }
+
+    fn zygote_spawned(&self, parent: &Zygote, zygote: &Zygote) {
+        assert_ne!(parent.pid().unwrap(), zygote.pid().unwrap());
+        record("spawned".into());
+    }
+
+    fn task_started(&self, _: &Zygote, task: &'static Location<'static>) {
+        assert_eq!(task.file(), file!());
+        record(format!("started {}", task.line()));
+    }
+
+    fn task_finished(&self, _: &Zygote, task: &'static Location<'static>, metrics: &TaskMetrics) {
+        assert!(metrics.bytes_sent > 0 && metrics.bytes_received > 0);
+        let fds = (metrics.fds_sent, metrics.fds_received);
+        record(format!("finished {} {fds:?}", task.line()));
+    }
+
+    fn task_panicked(&self, _: &Zygote, task: &'static Location<'static>, _: &WireError) {
+        record(format!("panicked {}", task.line()));
+    }
+
+    fn zygote_died(&self, _: &Zygote, status: Option<ExitStatus>) {
+        record(format!("died {:?}", status.and_then(|s| s.signal())));
+    }
+
+    fn channel_error(&self, _: &Zygote, error: &Error) {
+        record(format!("error {error}"));
+    }
+}
+
+#[test]
+fn events() {
+    assert!(zygote::set_observer(Recorder).is_ok());
+    assert!(zygote::set_observer(Recorder).is_err());
+
+    let line = line!() + 1;
+    let zygote = Zygote::new();
+    zygote.run(|_| {}, ());
+    let null = OwnedFd::from(std::fs::File::open("/dev/null").unwrap());
+    zygote.run(|fd: WireFd<OwnedFd>| fd, WireFd::new(null));
+    let _ = zygote.try_run::<_, ()>(|_| panic!("oops"), ());
+    let spawned = zygote.spawn();
+    let _ = spawned.try_run(|_| unsafe { libc::raise(libc::SIGKILL) }, ());
+
+    let events = EVENTS.lock().unwrap().clone();
+    let expected = [
+        "created".to_owned(),
+        format!("started {}", line + 1),
+        format!("finished {} (0, 0)", line + 1),
+        format!("started {}", line + 3),
+        format!("finished {} (1, 1)", line + 3),
+        format!("started {}", line + 4),
+        format!("panicked {}", line + 4),
+        format!("started {}", line + 5),
+        // the pidfd and the pipe of the new zygote
+        format!("finished {} (0, 2)", line + 5),
+        "spawned".to_owned(),
+        format!("started {}", line + 6),
+        // the sibling is also a child of this process, so its status is known
+        format!("died Some({})", libc::SIGKILL),
+    ];
+    assert_eq!(events, expected);
+}
@@ -0,0 +1,113 @@
+use zygote::{Capability, Zygote};
+
+fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+fn status(field: String) -> String {
+    let status = std::fs::read_to_string("/proc/self/status").unwrap();
+    let line = status
+        .lines()
+        .find_map(|line| line.strip_prefix(&format!("{field}:")))
+        .unwrap();
+    line.trim().to_owned()
+}
+
+fn capabilities(set: &str) -> u64 {
+    let set = Zygote::global().run(status, set.to_owned());
+    u64::from_str_radix(&set, 16).unwrap()
+}
+
+#[test]
+fn drop_privileges() {
+    if !is_root() {
+        return;
+    }
+
+    let zygote = Zygote::builder()
+        .uid(65534)
+        .gid(65534)
+        .groups([65534])
+        .capabilities([Capability::CAP_NET_BIND_SERVICE])
+        .ambient_capabilities([Capability::CAP_NET_BIND_SERVICE])
+        .build()
+        .unwrap();
+
+    let uid = zygote.run(|_| unsafe { (libc::getuid(), libc::geteuid()) }, ());
+    assert_eq!(uid, (65534, 65534));
+    let gid = zygote.run(|_| unsafe { (libc::getgid(), libc::getegid()) }, ());
+    assert_eq!(gid, (65534, 65534));
+    assert_eq!(zygote.run(status, "Groups".to_owned()), "65534");
+
+    let cap = 1 << Capability::CAP_NET_BIND_SERVICE.index();
+    for set in ["CapEff", "CapPrm", "CapBnd", "CapAmb"] {
+        let caps = zygote.run(status, set.to_owned());
+        assert_eq!(u64::from_str_radix(&caps, 16).unwrap(), cap, "{set}");
+    }
+
+    // the capabilities of other zygotes are unaffected
+    assert_ne!(capabilities("CapEff"), cap);
+}
+
+#[test]
+fn clear_groups() {
+    if !is_root() {
+        return;
+    }
+
+    // the groups of the parent aren't kept when switching users
+    let parent = Zygote::builder().groups([1, 2]).build().unwrap();
+    assert_eq!(parent.run(status, "Groups".to_owned()), "1 2");
+    let zygote = parent.run(
+        |_| {
+            let zygote = Zygote::builder().uid(65534).gid(65534).build();
+            zygote.unwrap().transfer()
+        },
+        (),
+    );
+    let zygote = zygote.into_zygote();
+    let groups = zygote.run(|_| unsafe { libc::getgroups(0, std::ptr::null_mut()) }, ());
+    assert_eq!(groups, 0);
+}
+
+#[test]
+fn no_new_privs() {
+    let zygote = Zygote::builder().no_new_privs(true).build().unwrap();
+    assert_eq!(zygote.run(status, "NoNewPrivs".to_owned()), "1");
+}
+
+#[test]
+fn failure() {
+    let builder = match is_root() {
+        // ambient capabilities must be permitted
+        true => Zygote::builder()
+            .capabilities([])
+            .ambient_capabilities([Capability::CAP_NET_BIND_SERVICE]),
+        // only privileged processes can change their user
+        false => Zygote::builder().uid(0),
+    };
+    let err = builder.build().err().unwrap();
+    assert!(err.to_string().contains("failed to"), "{err}");
+}
+
+#[test]
+fn parent_death() {
+    if !is_root() {
+        return;
+    }
+
+    // switching users clears the parent death signal, the zygote must set it again
+    let parent = Zygote::new();
+    let zygote = parent.run(
+        |_| {
+            let zygote = Zygote::builder().uid(65534).gid(65534).build();
+            zygote.unwrap().transfer()
+        },
+        (),
+    );
+    let zygote = zygote.into_zygote();
+    zygote.run(|_| (), ());
+
+    drop(parent);
+    assert!(zygote.try_run(|_| (), ()).is_err());
+}
@@ -0,0 +1,80 @@
+#![cfg(feature = "seccomp")]
+
+use zygote::{Address, Allowlist, Error, SeccompPolicy, Zygote};
+
+fn getppid() -> u32 {
+    unsafe { libc::getppid() as u32 }
+}
+
+#[test]
+fn compute() {
+    let zygote = Zygote::builder()
+        .seccomp(SeccompPolicy::compute())
+        .build()
+        .unwrap();
+    let res = zygote.run(|v: Vec<u64>| v.iter().sum::<u64>(), vec![1, 2, 3]);
+    assert_eq!(res, 6);
+
+    let err = zygote
+        .try_run(|_| std::fs::read("/proc/self/status").is_ok(), ())
+        .unwrap_err();
+    let Error::ForbiddenSyscall(syscall) = err else {
+        panic!("unexpected error: {err}");
+    };
+    assert!(syscall.starts_with("open"), "{syscall}");
+}
+
+#[test]
+fn deny() {
+    let zygote = Zygote::builder()
+        .seccomp(SeccompPolicy::deny([libc::SYS_getppid]))
+        .build()
+        .unwrap();
+    zygote.run(|_| std::fs::read("/proc/self/status").unwrap(), ());
+
+    let err = zygote.try_run(|_| getppid(), ()).unwrap_err();
+    assert!(matches!(err, Error::ForbiddenSyscall(syscall) if syscall == "getppid"));
+}
+
+#[test]
+fn deny_zygote_syscall() {
+    // the zygote can't receive tasks without reading from its pipe
+    let err = Zygote::builder()
+        .seccomp(SeccompPolicy::deny([libc::SYS_getppid, libc::SYS_read]))
+        .build()
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("read can't be denied"), "{err}");
+}
+
+#[test]
+fn spawned_zygote() {
+    let zygote = Zygote::builder()
+        .seccomp(SeccompPolicy::deny([libc::SYS_getppid]))
+        .build()
+        .unwrap();
+    let child = zygote.spawn_child();
+    child.run(|_| (), ());
+
+    // the policy is inherited, and violations are reported on the right pipe
+    let err = child.try_run(|_| getppid(), ()).unwrap_err();
+    assert!(matches!(err, Error::ForbiddenSyscall(_)));
+    zygote.run(|_| (), ());
+}
+
+#[test]
+fn connected_zygote() {
+    let addr = Address::abstract_name(format!("zygote-test-seccomp-{}", std::process::id()));
+    let zygote = Zygote::builder()
+        .seccomp(SeccompPolicy::deny([libc::SYS_getppid]))
+        .build()
+        .unwrap();
+    zygote
+        .listen(addr.clone(), Allowlist::current_user())
+        .unwrap();
+
+    let client = Zygote::connect(addr).unwrap();
+    let err = client.try_run(|_| getppid(), ()).unwrap_err();
+    assert!(matches!(err, Error::ForbiddenSyscall(syscall) if syscall == "getppid"));
+    zygote.run(|_| (), ());
+}
@@ -0,0 +1,146 @@
+use zygote::{Address, Allowlist, DropPolicy, Zygote};
+
+fn getppid() -> u32 {
+    unsafe { libc::getppid() as u32 }
+}
+
+fn getpid() -> u32 {
+    unsafe { libc::getpid() as u32 }
+}
+
+fn address(name: &str) -> Address {
+    Address::abstract_name(format!("zygote-test-{name}-{}", getpid()))
+}
+
+#[test]
+fn connect() {
+    let addr = address("connect");
+    let server = Zygote::new();
+    server
+        .listen(addr.clone(), Allowlist::current_user())
+        .unwrap();
+    let server_pid = server.run(|_| getpid(), ());
+
+    let client1 = Zygote::connect(addr.clone()).unwrap();
+    let client2 = Zygote::connect(addr).unwrap();
+
+    let pid1 = client1.run(|_| getpid(), ());
+    let pid2 = client2.run(|_| getpid(), ());
+
+    assert_ne!(pid1, pid2);
+    assert_eq!(client1.run(|_| getppid(), ()), server_pid);
+    assert_eq!(client2.run(|_| getppid(), ()), server_pid);
+
+    // the server keeps serving tasks
+    assert_eq!(server.run(|_| getpid(), ()), server_pid);
+}
+
+#[test]
+fn filesystem_socket() {
+    let path = std::env::temp_dir().join(format!("zygote-test-{}.sock", getpid()));
+    let server = Zygote::new();
+    server
+        .listen(path.as_path(), Allowlist::current_user())
+        .unwrap();
+
+    let client = Zygote::connect(path.as_path()).unwrap();
+    let res = client.run(|x: u32| x * 2, 21);
+    assert_eq!(res, 42);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn detached_server() {
+    let addr = address("detached");
+    let server = Zygote::builder()
+        .drop_policy(DropPolicy::Detach)
+        .build()
+        .unwrap();
+    server
+        .listen(addr.clone(), Allowlist::current_user())
+        .unwrap();
+    drop(server);
+
+    // the server keeps serving connections
+    let client = Zygote::connect(addr).unwrap();
+    let res = client.run(|x: u32| x * 2, 21);
+    assert_eq!(res, 42);
+
+    // the connection dies with the server, it might not get to reply
+    let _ = client.try_run(
+        |_| unsafe { libc::kill(libc::getppid(), libc::SIGKILL) },
+        (),
+    );
+}
+
+#[test]
+fn rejected_peer() {
+    let addr = address("rejected");
+    let server = Zygote::new();
+    server.listen(addr.clone(), Allowlist::new()).unwrap();
+
+    let err = Zygote::connect(addr).err().unwrap();
+    assert!(err.to_string().contains("not allowed"));
+}
+
+#[test]
+fn no_server() {
+    Zygote::connect(address("no-server")).err().unwrap();
+}
+
+#[test]
+fn connect_from_another_process() {
+    let addr = address("process");
+    let server = Zygote::new();
+    server
+        .listen(addr.clone(), Allowlist::current_user())
+        .unwrap();
+
+    let Address::Abstract(name) = addr else {
+        unreachable!()
+    };
+    let status = std::process::Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "client_process"])
+        .env("ZYGOTE_TEST_ADDR", String::from_utf8(name).unwrap())
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn client_process() {
+    // only does something when spawned from `connect_from_another_process`
+    let Ok(name) = std::env::var("ZYGOTE_TEST_ADDR") else {
+        return;
+    };
+    let client = Zygote::connect(Address::abstract_name(name)).unwrap();
+    let res = client.run(|x: u32| x * 2, 21);
+    assert_eq!(res, 42);
+}
+
+#[test]
+fn connect_as_another_user() {
+    if unsafe { libc::geteuid() } != 0 {
+        return;
+    }
+    // neither the server nor the client can look into the other process
+    let addr = address("user");
+    let server = Zygote::builder().uid(1000).gid(1000).build().unwrap();
+    server
+        .listen(addr.clone(), Allowlist::new().allow_uid(65534))
+        .unwrap();
+
+    let Address::Abstract(name) = addr else {
+        unreachable!()
+    };
+    let client = Zygote::builder().uid(65534).gid(65534).build().unwrap();
+    let res = client.run(
+        |name: Vec<u8>| {
+            let client = Zygote::connect(Address::abstract_name(name)).unwrap();
+            client.run(|_| getppid(), ())
+        },
+        name,
+    );
+    assert_eq!(res, server.run(|_| getpid(), ()));
+}